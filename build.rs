@@ -0,0 +1,364 @@
+// Converts the BDF fonts in `assets/fonts` into the glyph tables of `src/font.rs`,
+// and the XPM images in `assets/sprites` into the sprites of `src/sprite.rs`.
+//
+// Every glyph is stored with one bit per pixel, cut down to its bounding box,
+// so a font takes a fraction of the flash an RGB565 image of it would. Sprites
+// are stored as run-length encoded palette indices, with the transparent color
+// of the image as their key.
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const FONT_DIR: &'static str = "assets/fonts";
+const SPRITE_DIR: &'static str = "assets/sprites";
+
+struct Glyph {
+    code: i32,
+    advance: i32,
+    left: i32,
+    // Distance of the bottom of the bitmap above the baseline
+    bottom: i32,
+    width: i32,
+    height: i32,
+    rows: Vec<Vec<u8>>,
+}
+
+struct Font {
+    ascent: i32,
+    descent: i32,
+    default: Option<i32>,
+    glyphs: Vec<Glyph>,
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    // The colors of the image, where `None` is transparent
+    palette: Vec<Option<u16>>,
+    indices: Vec<u8>,
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    let mut out = File::create(&Path::new(&out_dir).join("fonts.rs")).unwrap();
+    for path in files(FONT_DIR, "bdf").iter() {
+        let font = match parse_bdf(&read(path)) {
+            Ok(font) => font,
+            Err(e) => panic!("{}: {}", path.display(), e),
+        };
+        write_font(&mut out, &name(path), &font);
+    }
+
+    let mut out = File::create(&Path::new(&out_dir).join("sprites.rs")).unwrap();
+    for path in files(SPRITE_DIR, "xpm").iter() {
+        let image = match parse_xpm(&read(path)) {
+            Ok(image) => image,
+            Err(e) => panic!("{}: {}", path.display(), e),
+        };
+        write_sprite(&mut out, &name(path), &image);
+    }
+}
+
+// The files in a directory with the given extension, in order of their names
+fn files(dir: &str, extension: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == extension))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn read(path: &Path) -> String {
+    let mut source = String::new();
+    File::open(path).unwrap().read_to_string(&mut source).unwrap();
+    source
+}
+
+// The name of the static generated from a file, which is its name in capitals
+fn name(path: &Path) -> String {
+    path.file_stem().unwrap().to_str().unwrap().to_uppercase()
+}
+
+fn parse_bdf(source: &str) -> Result<Font, String> {
+    let mut font = Font {
+        ascent: 0,
+        descent: 0,
+        default: None,
+        glyphs: Vec::new(),
+    };
+    let mut glyph: Option<Glyph> = None;
+    let mut in_bitmap = false;
+
+    for line in source.lines() {
+        let words: Vec<&str> = line.split(' ').filter(|word| !word.is_empty()).collect();
+        let keyword = match words.first() {
+            Some(&keyword) => keyword,
+            None => continue,
+        };
+
+        if in_bitmap {
+            if keyword == "ENDCHAR" {
+                in_bitmap = false;
+                let glyph = glyph.take().unwrap();
+                if glyph.rows.len() != glyph.height as usize {
+                    return Err(format!("glyph {} has {} rows instead of {}",
+                                       glyph.code, glyph.rows.len(), glyph.height));
+                }
+                // Glyphs without a character are left out
+                if glyph.code >= 0 {
+                    font.glyphs.push(glyph);
+                }
+            } else {
+                let row = try!(parse_hex(keyword));
+                glyph.as_mut().unwrap().rows.push(row);
+            }
+            continue;
+        }
+
+        let numbers: Vec<i32> = words[1 ..].iter().filter_map(|word| word.parse().ok()).collect();
+        match keyword {
+            "FONT_ASCENT" if numbers.len() == 1 => font.ascent = numbers[0],
+            "FONT_DESCENT" if numbers.len() == 1 => font.descent = numbers[0],
+            "DEFAULT_CHAR" if numbers.len() == 1 => font.default = Some(numbers[0]),
+            "STARTCHAR" => {
+                glyph = Some(Glyph {
+                    code: 0,
+                    advance: 0,
+                    left: 0,
+                    bottom: 0,
+                    width: 0,
+                    height: 0,
+                    rows: Vec::new(),
+                });
+            },
+            "ENCODING" | "DWIDTH" | "BBX" | "BITMAP" => {
+                let glyph = match glyph.as_mut() {
+                    Some(glyph) => glyph,
+                    None => return Err(format!("{} outside of a glyph", keyword)),
+                };
+                match keyword {
+                    "ENCODING" if numbers.len() >= 1 => glyph.code = numbers[0],
+                    "DWIDTH" if numbers.len() == 2 => glyph.advance = numbers[0],
+                    "BBX" if numbers.len() == 4 => {
+                        glyph.width = numbers[0];
+                        glyph.height = numbers[1];
+                        glyph.left = numbers[2];
+                        glyph.bottom = numbers[3];
+                    },
+                    "BITMAP" => in_bitmap = true,
+                    _ => return Err(format!("invalid line: {}", line)),
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if font.ascent == 0 && font.descent == 0 {
+        return Err("FONT_ASCENT and FONT_DESCENT are missing".to_string());
+    }
+    if font.glyphs.is_empty() {
+        return Err("the font has no glyphs".to_string());
+    }
+
+    font.glyphs.sort_by(|a, b| a.code.cmp(&b.code));
+
+    Ok(font)
+}
+
+fn parse_hex(row: &str) -> Result<Vec<u8>, String> {
+    if row.len() % 2 != 0 {
+        return Err(format!("odd number of digits in bitmap row {}", row));
+    }
+
+    let mut bytes = Vec::new();
+    for i in 0 .. row.len() / 2 {
+        match u8::from_str_radix(&row[2 * i .. 2 * i + 2], 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(format!("invalid bitmap row {}", row)),
+        }
+    }
+    Ok(bytes)
+}
+
+fn write_font<W: Write>(out: &mut W, name: &str, font: &Font) {
+    let default = font.default
+        .and_then(|code| font.glyphs.iter().position(|glyph| glyph.code == code))
+        .unwrap_or(0);
+
+    writeln!(out, "pub static {}: Font = Font {{", name).unwrap();
+    let width = font.glyphs.iter().map(|glyph| glyph.advance).max().unwrap();
+    writeln!(out, "    width: {},", width).unwrap();
+    writeln!(out, "    height: {},", font.ascent + font.descent).unwrap();
+    writeln!(out, "    default: {},", default).unwrap();
+
+    let mut bitmap = Vec::new();
+    writeln!(out, "    glyphs: &[").unwrap();
+    for glyph in font.glyphs.iter() {
+        writeln!(out, "        Glyph {{ code: {}, advance: {}, left: {}, top: {}, width: {}, height: {}, offset: {} }},",
+                 glyph.code, glyph.advance, glyph.left, font.ascent - glyph.bottom - glyph.height,
+                 glyph.width, glyph.height,
+                 bitmap.len()).unwrap();
+
+        // Only as many bytes per row as the width needs
+        let stride = (glyph.width as usize + 7) / 8;
+        for row in glyph.rows.iter() {
+            for i in 0 .. stride {
+                bitmap.push(row.get(i).cloned().unwrap_or(0));
+            }
+        }
+    }
+    writeln!(out, "    ],").unwrap();
+
+    writeln!(out, "    bitmap: &[").unwrap();
+    for bytes in bitmap.chunks(16) {
+        let mut line = "       ".to_string();
+        for byte in bytes.iter() {
+            line.push_str(&format!(" 0x{:02x},", byte));
+        }
+        writeln!(out, "{}", line).unwrap();
+    }
+    writeln!(out, "    ],").unwrap();
+    writeln!(out, "}};").unwrap();
+}
+
+// Reads the strings of an XPM 3 image: the size, the colors and the rows of
+// pixels. Colors are either `None` or given as `#rrggbb`.
+fn parse_xpm(source: &str) -> Result<Image, String> {
+    // Everything between a pair of quotes, the rest is C around it
+    let strings: Vec<&str> = source.split('"').enumerate()
+        .filter(|&(i, _)| i % 2 == 1)
+        .map(|(_, string)| string)
+        .collect();
+    let header = match strings.first() {
+        Some(header) => header,
+        None => return Err("no image in the file".to_string()),
+    };
+
+    let numbers: Vec<usize> = header.split(' ').filter_map(|word| word.parse().ok()).collect();
+    if numbers.len() < 4 || numbers[3] == 0 {
+        return Err(format!("invalid header {}", header));
+    }
+    let (width, height, colors, chars) = (numbers[0], numbers[1], numbers[2], numbers[3]);
+    if colors > 256 {
+        return Err(format!("{} colors, when a sprite has at most 256", colors));
+    }
+    if strings.len() < 1 + colors + height {
+        return Err("the image is cut short".to_string());
+    }
+
+    let mut keys = Vec::new();
+    let mut palette = Vec::new();
+    for line in strings[1 .. 1 + colors].iter() {
+        if line.len() < chars {
+            return Err(format!("invalid color {}", line));
+        }
+        let words: Vec<&str> = line[chars ..].split(' ').filter(|word| !word.is_empty()).collect();
+        let value = match words.iter().position(|&word| word == "c").and_then(|i| words.get(i + 1)) {
+            Some(value) => value,
+            None => return Err(format!("color {} has no visual of type c", line)),
+        };
+
+        keys.push(&line[.. chars]);
+        palette.push(match *value {
+            "None" | "none" => None,
+            _ => Some(try!(parse_color(value))),
+        });
+    }
+
+    let mut indices = Vec::new();
+    for row in strings[1 + colors .. 1 + colors + height].iter() {
+        if row.len() != width * chars {
+            return Err(format!("row {} is not {} pixels wide", row, width));
+        }
+        for i in 0 .. width {
+            let key = &row[i * chars .. (i + 1) * chars];
+            match keys.iter().position(|&k| k == key) {
+                Some(index) => indices.push(index as u8),
+                None => return Err(format!("unknown color {}", key)),
+            }
+        }
+    }
+
+    Ok(Image {
+        width: width,
+        height: height,
+        palette: palette,
+        indices: indices,
+    })
+}
+
+// Converts `#rrggbb` to RGB565
+fn parse_color(value: &str) -> Result<u16, String> {
+    if !value.starts_with('#') || value.len() != 7 {
+        return Err(format!("invalid color {}", value));
+    }
+    let rgb = match u32::from_str_radix(&value[1 ..], 16) {
+        Ok(rgb) => rgb,
+        Err(_) => return Err(format!("invalid color {}", value)),
+    };
+    let (r, g, b) = ((rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff);
+    Ok(((r >> 3) << 11 | (g >> 2) << 5 | b >> 3) as u16)
+}
+
+fn write_sprite<W: Write>(out: &mut W, name: &str, image: &Image) {
+    // Transparent pixels get a color of their own that is left out when drawn
+    let colors: Vec<u16> = image.palette.iter().filter_map(|&color| color).collect();
+    let key = if image.palette.iter().any(|color| color.is_none()) {
+        (0 .. 0xffff).find(|key| !colors.contains(key))
+    } else {
+        None
+    };
+    let palette: Vec<u16> = image.palette.iter().map(|&color| color.or(key).unwrap()).collect();
+
+    writeln!(out, "pub static {}: Sprite<'static> = Sprite {{", name).unwrap();
+    writeln!(out, "    width: {},", image.width).unwrap();
+    writeln!(out, "    height: {},", image.height).unwrap();
+    writeln!(out, "    pixels: Pixels::IndexedRle {{").unwrap();
+
+    let mut line = "        palette: &[".to_string();
+    for (i, color) in palette.iter().enumerate() {
+        if i > 0 {
+            line.push_str(", ");
+        }
+        line.push_str(&format!("0x{:04x}", color));
+    }
+    writeln!(out, "{}],", line).unwrap();
+
+    writeln!(out, "        runs: &[").unwrap();
+    let runs = encode_indexed_rle(&image.indices);
+    for bytes in runs.chunks(16) {
+        let mut line = "           ".to_string();
+        for byte in bytes.iter() {
+            line.push_str(&format!(" {},", byte));
+        }
+        writeln!(out, "{}", line).unwrap();
+    }
+    writeln!(out, "        ],").unwrap();
+
+    writeln!(out, "    }},").unwrap();
+    match key {
+        Some(key) => writeln!(out, "    key: Some(0x{:04x}),", key).unwrap(),
+        None => writeln!(out, "    key: None,").unwrap(),
+    }
+    writeln!(out, "}};").unwrap();
+}
+
+// Pairs of the number of pixels in a run, at most 255, and their index
+fn encode_indexed_rle(indices: &[u8]) -> Vec<u8> {
+    let mut runs: Vec<u8> = Vec::new();
+    for &index in indices.iter() {
+        let len = runs.len();
+        if len >= 2 && runs[len - 1] == index && runs[len - 2] < 0xff {
+            runs[len - 2] += 1;
+        } else {
+            runs.push(1);
+            runs.push(index);
+        }
+    }
+    runs
+}
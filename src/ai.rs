@@ -1,35 +1,82 @@
-use {GameEnv, Circle};
+use {GameEnv, Circle, MAX_CIRCLES};
 use core::prelude::*;
 
+use controller::{Controller, RELEASED};
+use state::{Game, State};
+
+/// Simulates the buttons of all circles, steering them towards the gaps.
+pub struct Ai {
+    // Whether the buttons were let go of in the previous frame
+    released: bool,
+}
+
+impl Ai {
+    pub fn new() -> Ai {
+        Ai { released: false }
+    }
+
+    /// The buttons for the next frame, where `simulated` holds the result of
+    /// `get_simulate_buttons` for the frame once it has been worked out, so
+    /// several circles played by the AI only work it out once.
+    pub fn shared_buttons(&mut self, game: &Game, simulated: &mut Option<u32>) -> u32 {
+        // The game over screen waits for a button to be pressed again, so
+        // let go of them every other frame there
+        self.released = game.state() == State::GameOver && !self.released;
+        if self.released {
+            return RELEASED;
+        }
+
+        match *simulated {
+            Some(buttons) => buttons,
+            None => {
+                let buttons = get_simulate_buttons(game.env());
+                *simulated = Some(buttons);
+                buttons
+            },
+        }
+    }
+}
+
+impl Controller for Ai {
+    fn buttons(&mut self, game: &Game) -> u32 {
+        self.shared_buttons(game, &mut None)
+    }
+}
+
 pub fn get_simulate_buttons(env: &GameEnv) -> u32 {
 
-    // The y coordinate of the obstical is given by the frame counter in the GameEnv
-    let gap_y = env.frame;
+    // Steer towards the lowest obstacle that has not yet passed all circles
+    let bottom = env.circles.iter().map(|circle| circle.rect.dy + 50).max().unwrap_or(0);
+    let obstacle = match env.obstacles.iter().find(|obstacle| obstacle.pos <= bottom) {
+        Some(obstacle) => obstacle,
+        None => match env.obstacles.last() {
+            Some(obstacle) => obstacle,
+            None => return RELEASED,
+        },
+    };
+
+    let gap_y = obstacle.pos;
 
-    let (gap_1_start, gap_1_end) = env.obstacle.gap1;
+    let (gap_1_start, gap_1_end) = obstacle.gap1;
     let gap1 = ((gap_1_start + gap_1_end) / 2, gap_y);
 
-    let button_pattern = match env.obstacle.gap2 {
-        Some((gap_2_start, gap_2_end)) => {
-            let gap2 = ((gap_2_start + gap_2_end) / 2, gap_y);
-
-            let diff1_1 = distance(&env.circle1, gap1);
-            let diff2_1 = distance(&env.circle2, gap1);
-            let diff1_2 = distance(&env.circle1, gap2);
-            let diff2_2 = distance(&env.circle2, gap2);
-
-            if diff1_1 > diff1_2 && diff2_1 > diff2_2 {
-                both_go_to_gap(env, gap2)
-            } else if diff1_1 < diff1_2 && diff2_1 < diff2_2 {
-                both_go_to_gap(env, gap1)
-            } else if diff1_1 < diff1_2 {
-                go_to_gap(env, 0, gap1) | go_to_gap(env, 1, gap2)
-            } else {
-                go_to_gap(env, 0, gap2) | go_to_gap(env, 1, gap1)
+    // Every circle heads for the gap closest to it
+    let mut targets = [gap1; MAX_CIRCLES];
+    if let Some((gap_2_start, gap_2_end)) = obstacle.gap2 {
+        let gap2 = ((gap_2_start + gap_2_end) / 2, gap_y);
+
+        for (i, circle) in env.circles.iter().enumerate() {
+            if distance(circle, gap2) < distance(circle, gap1) {
+                targets[i] = gap2;
             }
-        },
-        None => both_go_to_gap(env, gap1)
-    };
+        }
+    }
+
+    let mut button_pattern = 0;
+
+    for i in 0 .. env.circles.len() {
+        button_pattern |= go_to_gap(env, i, &targets);
+    }
 
     !button_pattern
 
@@ -45,54 +92,39 @@ fn distance(circle: &Circle, gap_center: (i32,i32)) -> i32 {
     pow_2(x - g_x) + pow_2(y - g_y)
 }
 
-fn both_go_to_gap(env: &GameEnv, gap_center: (i32, i32)) -> u32 {
-
-    let mut buttons = 0;
+fn go_to_gap(env: &GameEnv, index: usize, targets: &[(i32, i32)]) -> u32 {
 
+    let circle = &env.circles[index];
+    let gap_center = targets[index];
     let gap_x = gap_center.0;
 
-    // Move circle 1 on x axis
-    if (env.circle1.rect.dx + 25) < gap_x {
-        buttons |= 0x4;
+    // Move on x axis
+    let mut buttons = if (circle.rect.dx + 25) < gap_x {
+        circle.keys.right
     } else {
-        buttons |= 0x1;
-    }
+        circle.keys.left
+    };
 
-    // Move circle 2 on x axis
-    if (env.circle2.rect.dx + 25) < gap_x {
-        buttons |= 0x40;
-    } else {
-        buttons |= 0x10;
+    // Circles heading for the same gap take turns. The one closest to the gap
+    // stays up, while the others move down and out of its way.
+    let diff = distance(circle, gap_center);
+    let mut sharing = false;
+    let mut closest = true;
+    for (j, other) in env.circles.iter().enumerate() {
+        if j != index && targets[j] == gap_center {
+            let other_diff = distance(other, gap_center);
+            sharing = true;
+            closest = closest && (diff < other_diff || (diff == other_diff && index > j));
+        }
     }
 
-    let diff1 = distance(&env.circle1, gap_center);
-    let diff2 = distance(&env.circle2, gap_center);
-
-    if diff1 < diff2 {
-        buttons |= 0x80;
-        if (env.circle1.rect.dy + 25) > 165 {
-            buttons |= 0x2;
+    if sharing && closest {
+        if (circle.rect.dy + 25) > 165 {
+            buttons |= circle.keys.up;
         }
     } else {
-        buttons |= 0x8;
-        if (env.circle2.rect.dy + 25) > 165 {
-            buttons |= 0x20;
-        }
+        buttons |= circle.keys.down;
     }
 
     buttons
 }
-
-fn go_to_gap(env: &GameEnv, circle: u32, gap_center: (i32, i32)) -> u32 {
-
-    let gap_x = gap_center.0;
-
-    if circle == 0 {
-        let x = env.circle1.rect.dx + 25;
-        0x8 | if x < gap_x { 0x4 } else { 0x1 }
-    } else {
-        let x = env.circle2.rect.dx + 25;
-        0x80 | if x < gap_x { 0x40 } else { 0x10 }
-    }
-
-}
@@ -0,0 +1,114 @@
+// Color animation of the circles.
+//
+// Every circle follows a `Profile`, which starts from a color and turns its
+// hue from one frame to the next and from one point of the outline to the
+// next, so the colors run around the circle. The saturation and brightness can
+// swing below those of the color and back. A profile that changes nothing
+// keeps the color exactly as it is.
+
+use core::prelude::*;
+
+use color::Rgb565;
+
+// Hues are kept in 1/16 degrees
+const HUE_STEPS: i32 = 360 * 16;
+
+#[derive(Copy, Clone)]
+pub struct Profile {
+    pub color: Rgb565,
+    /// How far the hue turns every frame, in 1/16 degrees.
+    pub hue_speed: i16,
+    /// How far the hue turns from one point of the outline to the next, in 1/16 degrees.
+    pub hue_step: i16,
+    /// How far the saturation and brightness swing below those of `color`.
+    pub saturation_swing: u8,
+    pub value_swing: u8,
+    /// Number of frames a swing down and back up takes.
+    pub swing_frames: u16,
+}
+
+/// Where a circle is in its profile.
+pub struct Animation {
+    profile: Profile,
+    // The color of the profile as hue, saturation and value
+    base: (i32, u8, u8),
+    frame: u32,
+    // Hue of the first point, and the saturation and value in this frame
+    hue: i32,
+    saturation: u8,
+    value: u8,
+    // Color of the first point in this frame
+    color: Rgb565,
+}
+
+impl Animation {
+    pub fn new(profile: Profile) -> Animation {
+        let (hue, saturation, value) = profile.color.to_hsv();
+        let mut animation = Animation {
+            profile: profile,
+            base: (hue as i32 * 16, saturation, value),
+            frame: 0,
+            hue: 0,
+            saturation: 0,
+            value: 0,
+            color: profile.color,
+        };
+        animation.update();
+        animation
+    }
+
+    /// Moves on to the next frame.
+    pub fn advance(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+        self.update();
+    }
+
+    /// The color of a point of the outline, counted from the first one.
+    pub fn color(&self, point: usize) -> Rgb565 {
+        if self.profile.hue_step == 0 {
+            return self.color;
+        }
+
+        let hue = wrap_hue(self.hue + point as i32 * self.profile.hue_step as i32);
+        Rgb565::hsv((hue / 16) as u16, self.saturation, self.value)
+    }
+
+    fn update(&mut self) {
+        let profile = self.profile;
+        let (hue, saturation, value) = self.base;
+
+        let frames = HUE_STEPS as u32;
+        let turned = (self.frame % frames) as i32 * profile.hue_speed as i32;
+        self.hue = wrap_hue(hue + turned);
+
+        let swing = triangle(self.frame, profile.swing_frames as u32);
+        self.saturation = saturation.saturating_sub((profile.saturation_swing as u32 * swing / 255) as u8);
+        self.value = value.saturating_sub((profile.value_swing as u32 * swing / 255) as u8);
+
+        let still = profile.hue_speed == 0 && profile.saturation_swing == 0 && profile.value_swing == 0;
+        self.color = if still {
+            profile.color
+        } else {
+            Rgb565::hsv((self.hue / 16) as u16, self.saturation, self.value)
+        };
+    }
+}
+
+fn wrap_hue(hue: i32) -> i32 {
+    (hue % HUE_STEPS + HUE_STEPS) % HUE_STEPS
+}
+
+// Goes from 0 up to 255 and back down to 0 over `period` frames
+fn triangle(frame: u32, period: u32) -> u32 {
+    if period < 2 {
+        return 0;
+    }
+
+    let phase = frame % period;
+    let half = period / 2;
+    if phase < half {
+        phase * 255 / half
+    } else {
+        (period - phase) * 255 / (period - half)
+    }
+}
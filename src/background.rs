@@ -0,0 +1,165 @@
+// The scrolling background beneath the circles and obstacles.
+//
+// The bottom of the playing field is made of horizontal bands, each with a
+// pattern that repeats every 32 columns and moves to the left at a speed of its
+// own, so the slower ones look further away. Scrolling only draws the pixels
+// whose part of the pattern changed, before anything else is drawn for the
+// frame, and marks the lines it drew on as dirty so the circles, obstacles and
+// particles on top are drawn over it again. Whatever erases something in the
+// playing field draws the background beneath it with `Background::color`.
+//
+// The bands move with the refreshes of the panel, which the EBI interrupt
+// counts in `display::scroll_offset`, so they keep their speed when a frame
+// takes longer than a refresh. The patterns are kept in flash rather than in
+// the spare columns of the virtual display, as the two pages leave only 32 of
+// them.
+
+use core::prelude::*;
+use core::cmp;
+
+use Rectangle;
+use display::Dirty;
+use framebuffer::FrameBuffer;
+use theme::Theme;
+
+pub struct Band {
+    pub top: usize,
+    /// A row of the pattern for every line of the band, with the leftmost
+    /// column in the highest bit.
+    pub rows: &'static [u32],
+    /// How far the band moves to the left every refresh, in 1/16 pixels.
+    pub speed: u32,
+}
+
+pub static BANDS: [Band; 3] = [
+    // Stars
+    Band {
+        top: 192,
+        rows: &[
+            0x00000000, 0x04000000, 0x00000000, 0x00000000, 0x00000200,
+            0x00000000, 0x00000000, 0x00040000, 0x00000000, 0x00000000,
+            0x00000000, 0x00000008, 0x00000000, 0x00000000, 0x20000000,
+            0x00000000, 0x00000000, 0x00004000, 0x00000000, 0x00000000,
+        ],
+        speed: 2,
+    },
+    // Hills
+    Band {
+        top: 215,
+        rows: &[
+            0x1c1c0000, 0x3ffe0000, 0x7fff0000, 0x7fff0000, 0xffff8000,
+            0xffff8000, 0xffffc001, 0xffffe3e3, 0xffffffff,
+        ],
+        speed: 6,
+    },
+    // The ground, with dashes running by
+    Band {
+        top: 224,
+        rows: &[
+            0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0xff00ff00,
+            0x00000000, 0x00000000, 0x00000000, 0x00000000, 0xf0f0f0f0,
+            0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000,
+            0x00000000,
+        ],
+        speed: 16,
+    },
+];
+
+// Most refreshes the bands move by at once, so they don't jump after a pause
+const MAX_ELAPSED: u32 = 4;
+
+pub struct Background {
+    // The scroll offset the bands were last moved to
+    offset: Option<u32>,
+    // How far each band has moved, in 1/16 pixels
+    positions: [u32; 3],
+}
+
+impl Background {
+    pub fn new() -> Background {
+        Background {
+            offset: None,
+            positions: [0; 3],
+        }
+    }
+
+    /// The color of the background at a pixel.
+    pub fn color(&self, theme: &Theme, x: usize, y: usize) -> u16 {
+        for (i, band) in BANDS.iter().enumerate() {
+            if y >= band.top && y < band.top + band.rows.len() {
+                let row = band.rows[y - band.top].rotate_left(self.shift(i));
+                return if row & column(x) != 0 { theme.pattern.0 } else { theme.background.0 };
+            }
+        }
+        theme.background.0
+    }
+
+    /// Draws the bands over whatever is beneath them.
+    pub fn draw<F: FrameBuffer>(&self, fb: &mut F, theme: &Theme) {
+        for band in BANDS.iter() {
+            for y in band.top .. band.top + band.rows.len() {
+                for x in 0 .. fb.width() {
+                    let color = self.color(theme, x, y);
+                    fb.set_pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// Moves the bands on to a scroll offset, which grows by one every refresh,
+    /// and marks the lines that changed as dirty.
+    pub fn scroll<F: FrameBuffer>(&mut self, fb: &mut F, theme: &Theme, offset: u32, dirty: &mut Dirty) {
+        let elapsed = match self.offset {
+            Some(last) => cmp::min(offset.wrapping_sub(last), MAX_ELAPSED),
+            None => 0,
+        };
+        self.offset = Some(offset);
+
+        for (i, band) in BANDS.iter().enumerate() {
+            let old_shift = self.shift(i);
+            self.positions[i] = self.positions[i].wrapping_add(elapsed * band.speed);
+            let new_shift = self.shift(i);
+
+            // Nothing changes if the band stayed in place, or if its pattern
+            // has the color of the background
+            if old_shift == new_shift || theme.pattern == theme.background {
+                continue;
+            }
+
+            // The lines of the band that changed, from the first to the last
+            let (mut top, mut bottom) = (None, band.top);
+            for (line, &row) in band.rows.iter().enumerate() {
+                let new_row = row.rotate_left(new_shift);
+                let changed = row.rotate_left(old_shift) ^ new_row;
+                if changed == 0 {
+                    continue;
+                }
+
+                let y = band.top + line;
+                for x in 0 .. fb.width() {
+                    if changed & column(x) != 0 {
+                        let color = if new_row & column(x) != 0 { theme.pattern.0 } else { theme.background.0 };
+                        fb.set_pixel(x, y, color);
+                    }
+                }
+                top = top.or(Some(y));
+                bottom = y + 1;
+            }
+
+            if let Some(top) = top {
+                dirty.mark(Rectangle::new(0, top as i32, fb.width() as i32, (bottom - top) as i32));
+            }
+        }
+    }
+
+    // How far the rows of a band are rotated for where it has moved to, which
+    // puts the pixel at `x` in `column(x)`
+    fn shift(&self, band: usize) -> u32 {
+        (self.positions[band] >> 4) % 32
+    }
+}
+
+// The bit of a row for a column of the screen
+fn column(x: usize) -> u32 {
+    1 << (31 - x % 32)
+}
@@ -0,0 +1,101 @@
+// Colors in the pixel format of the TFT.
+//
+// A pixel is 16 bits, with 5 bits of red, 6 bits of green and 5 bits of blue
+// from the highest bit down. The frame buffer and the drawing primitives take
+// the raw `u16`, which is the `.0` of an `Rgb565`.
+
+use core::prelude::*;
+use core::cmp;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Rgb565(pub u16);
+
+pub const BLACK: Rgb565 = Rgb565(0x0000);
+pub const WHITE: Rgb565 = Rgb565(0xffff);
+pub const GREY: Rgb565 = Rgb565(0x8410);
+pub const RED: Rgb565 = Rgb565(0xf800);
+pub const GREEN: Rgb565 = Rgb565(0x07e0);
+pub const BLUE: Rgb565 = Rgb565(0x001f);
+pub const YELLOW: Rgb565 = Rgb565(0xffe0);
+pub const CYAN: Rgb565 = Rgb565(0x07ff);
+pub const MAGENTA: Rgb565 = Rgb565(0xf81f);
+
+impl Rgb565 {
+    /// The closest color to 8 bit red, green and blue.
+    pub fn rgb(red: u8, green: u8, blue: u8) -> Rgb565 {
+        Rgb565((red as u16 >> 3) << 11 | (green as u16 >> 2) << 5 | blue as u16 >> 3)
+    }
+
+    /// A color from its hue in degrees, and its saturation and value from 0 to 255.
+    pub fn hsv(hue: u16, saturation: u8, value: u8) -> Rgb565 {
+        let (s, v) = (saturation as u32, value as u32);
+        let sector = (hue % 360) / 60;
+        // How far the hue is into its sector, from 0 to 255
+        let f = ((hue % 360) % 60) as u32 * 255 / 59;
+
+        let p = (v * (255 - s) / 255) as u8;
+        let q = (v * (255 - s * f / 255) / 255) as u8;
+        let t = (v * (255 - s * (255 - f) / 255) / 255) as u8;
+        let v = value;
+
+        match sector {
+            0 => Rgb565::rgb(v, t, p),
+            1 => Rgb565::rgb(q, v, p),
+            2 => Rgb565::rgb(p, v, t),
+            3 => Rgb565::rgb(p, q, v),
+            4 => Rgb565::rgb(t, p, v),
+            _ => Rgb565::rgb(v, p, q),
+        }
+    }
+
+    /// The hue in degrees, and the saturation and value from 0 to 255.
+    pub fn to_hsv(self) -> (u16, u8, u8) {
+        let (r, g, b) = self.to_rgb();
+        let (r, g, b) = (r as i32, g as i32, b as i32);
+        let max = cmp::max(r, cmp::max(g, b));
+        let min = cmp::min(r, cmp::min(g, b));
+        let delta = max - min;
+
+        if delta == 0 {
+            return (0, 0, max as u8);
+        }
+
+        let hue = if max == r {
+            60 * (g - b) / delta
+        } else if max == g {
+            120 + 60 * (b - r) / delta
+        } else {
+            240 + 60 * (r - g) / delta
+        };
+
+        (((hue + 360) % 360) as u16, (255 * delta / max) as u8, max as u8)
+    }
+
+    /// The red, green and blue channels scaled up to 8 bits.
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        let (r, g, b) = self.channels();
+        ((r << 3 | r >> 2) as u8, (g << 2 | g >> 4) as u8, (b << 3 | b >> 2) as u8)
+    }
+
+    /// Mixes in `amount` 255ths of another color, channel by channel.
+    pub fn blend(self, other: Rgb565, amount: u8) -> Rgb565 {
+        let (r1, g1, b1) = self.channels();
+        let (r2, g2, b2) = other.channels();
+        let mix = |from: u16, to: u16| {
+            (from as i32 + (to as i32 - from as i32) * amount as i32 / 255) as u16
+        };
+        Rgb565(mix(r1, r2) << 11 | mix(g1, g2) << 5 | mix(b1, b2))
+    }
+
+    /// Darkens the color to `amount` 255ths of its brightness, channel by channel.
+    pub fn scale(self, amount: u8) -> Rgb565 {
+        let (r, g, b) = self.channels();
+        let scale = |channel: u16| (channel as u32 * amount as u32 / 255) as u16;
+        Rgb565(scale(r) << 11 | scale(g) << 5 | scale(b))
+    }
+
+    // The channels at their own precision
+    fn channels(self) -> (u16, u16, u16) {
+        (self.0 >> 11, (self.0 >> 5) & 0x3f, self.0 & 0x1f)
+    }
+}
@@ -0,0 +1,135 @@
+use core::prelude::*;
+use collections::vec::Vec;
+
+use GameEnv;
+use ai::Ai;
+use replay::Replay;
+use state::Game;
+#[cfg(not(feature = "sim"))]
+use gamepad::Gamepad;
+
+// Buttons used by all the circles together
+pub const BUTTONS: u32 = 0xffff;
+
+// Button state with nothing pressed
+pub const RELEASED: u32 = !0;
+
+/// A source of button states for the game.
+///
+/// Button states are active low, like the pins of the gamepad on Port C.
+pub trait Controller {
+    /// Returns the button state to use for the next frame of `game`.
+    fn buttons(&mut self, game: &Game) -> u32;
+
+    /// Called once the frame has been played. `rng_state` is the random state
+    /// from before the frame, and `restarted` tells if it started a new round.
+    fn frame_done(&mut self, _env: &GameEnv, _rng_state: u8, _restarted: bool) {}
+
+    /// Tells if the controller has run out of input, like a replay that has ended.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+/// Controls each circle with its own controller, in the order of the circles.
+///
+/// Only the buttons of its own circle are taken from each controller. Circles
+/// without a controller keep their buttons released. The circles played by the
+/// AI share the buttons it picks for all of them, so it only runs once a frame.
+pub struct Players<'a> {
+    pub controllers: Vec<Input<'a>>,
+}
+
+impl<'a> Controller for Players<'a> {
+    fn buttons(&mut self, game: &Game) -> u32 {
+        let mut buttons = RELEASED;
+        let mut simulated = None;
+        for (controller, circle) in self.controllers.iter_mut().zip(game.env().circles.iter()) {
+            let mask = circle.keys.mask();
+            let own = match *controller {
+                Input::Ai(ref mut ai) => ai.shared_buttons(game, &mut simulated),
+                ref mut controller => controller.buttons(game),
+            };
+            buttons = (buttons & !mask) | (own & mask);
+        }
+        buttons
+    }
+
+    fn frame_done(&mut self, env: &GameEnv, rng_state: u8, restarted: bool) {
+        for controller in self.controllers.iter_mut() {
+            controller.frame_done(env, rng_state, restarted);
+        }
+    }
+}
+
+/// Plays back a fixed sequence of `(buttons, frames)` steps.
+///
+/// Once the sequence is done it either starts over or keeps every button released.
+pub struct Script<'a> {
+    steps: &'a [(u32, u32)],
+    repeat: bool,
+    step: usize,
+    frames: u32,
+}
+
+impl<'a> Script<'a> {
+    pub fn new(steps: &'a [(u32, u32)], repeat: bool) -> Script<'a> {
+        assert!(!repeat || steps.iter().any(|&(_, frames)| frames > 0));
+
+        Script {
+            steps: steps,
+            repeat: repeat,
+            step: 0,
+            frames: 0,
+        }
+    }
+}
+
+impl<'a> Controller for Script<'a> {
+    fn buttons(&mut self, _game: &Game) -> u32 {
+        while self.step < self.steps.len() && self.frames >= self.steps[self.step].1 {
+            self.step += 1;
+            self.frames = 0;
+            if self.step == self.steps.len() && self.repeat {
+                self.step = 0;
+            }
+        }
+
+        if self.step == self.steps.len() {
+            return RELEASED;
+        }
+
+        self.frames += 1;
+        self.steps[self.step].0
+    }
+}
+
+/// The controllers a circle can be assigned to at runtime.
+///
+/// A replay plays a circle with the buttons recorded for it, which only leads
+/// to the recorded game if every circle replays the same recording.
+pub enum Input<'a> {
+    #[cfg(not(feature = "sim"))]
+    Gamepad(Gamepad),
+    Ai(Ai),
+    Script(Script<'a>),
+    Replay(Replay<'a>),
+}
+
+impl<'a> Controller for Input<'a> {
+    fn buttons(&mut self, game: &Game) -> u32 {
+        match *self {
+            #[cfg(not(feature = "sim"))]
+            Input::Gamepad(ref mut gamepad) => gamepad.buttons(game),
+            Input::Ai(ref mut ai) => ai.buttons(game),
+            Input::Script(ref mut script) => script.buttons(game),
+            Input::Replay(ref mut replay) => replay.buttons(game),
+        }
+    }
+
+    fn frame_done(&mut self, env: &GameEnv, rng_state: u8, restarted: bool) {
+        if let Input::Replay(ref mut replay) = *self {
+            replay.frame_done(env, rng_state, restarted);
+        }
+    }
+}
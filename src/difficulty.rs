@@ -0,0 +1,111 @@
+// Difficulty curve of the game.
+//
+// The curve is a table of points, each giving the difficulty at a score.
+// Between two points the settings are interpolated linearly, and past the last
+// point the game stays at the difficulty of that point. The gaps are always
+// kept narrower than the part of the screen they are placed in.
+
+use core::prelude::*;
+use core::cmp;
+
+use display::WIDTH;
+
+#[derive(Copy, Clone)]
+pub struct Level {
+    // Score at which this level is reached
+    pub score: i32,
+    // Lines the obstacles advance per frame, in 1/256 lines
+    pub speed: i32,
+    // Width of the gap when an obstacle has a single gap
+    pub gap: i32,
+    // Width of each gap when an obstacle has two gaps
+    pub split_gap: i32,
+    // Chance of an obstacle having two gaps, out of 256
+    pub split_chance: i32,
+    // Lines between the start of two consecutive obstacles
+    pub spacing: i32,
+}
+
+pub static CURVE: [Level; 4] = [
+    Level { score: 0,  speed: 256, gap: 90, split_gap: 70, split_chance: 128, spacing: 180 },
+    Level { score: 10, speed: 320, gap: 84, split_gap: 66, split_chance: 150, spacing: 160 },
+    Level { score: 25, speed: 384, gap: 76, split_gap: 62, split_chance: 180, spacing: 140 },
+    Level { score: 50, speed: 512, gap: 68, split_gap: 58, split_chance: 210, spacing: 120 },
+];
+
+/// The difficulty at the given score.
+pub fn level(score: u32) -> Level {
+    at(&CURVE, score as i32)
+}
+
+/// Interpolates the difficulty at the given score from a curve.
+pub fn at(curve: &[Level], score: i32) -> Level {
+    let mut i = 0;
+    while i + 1 < curve.len() && curve[i + 1].score <= score {
+        i += 1;
+    }
+    if i + 1 == curve.len() || score <= curve[i].score {
+        return limit(curve[i]);
+    }
+
+    let from = &curve[i];
+    let to = &curve[i + 1];
+    let lerp = |a: i32, b: i32| a + (b - a) * (score - from.score) / (to.score - from.score);
+
+    limit(Level {
+        score: score,
+        speed: lerp(from.speed, to.speed),
+        gap: lerp(from.gap, to.gap),
+        split_gap: lerp(from.split_gap, to.split_gap),
+        split_chance: lerp(from.split_chance, to.split_chance),
+        spacing: lerp(from.spacing, to.spacing),
+    })
+}
+
+// Keeps a single gap within the width of the screen, and each of two gaps
+// within its half of it, with at least one column of obstacle beside them
+fn limit(level: Level) -> Level {
+    let clamp = |value: i32, max: i32| cmp::min(cmp::max(value, 1), max);
+
+    Level {
+        gap: clamp(level.gap, WIDTH as i32 - 1),
+        split_gap: clamp(level.split_gap, WIDTH as i32 / 2 - 1),
+        .. level
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{at, level, Level, CURVE};
+    use display::WIDTH;
+
+    fn point(score: i32, gap: i32, split_gap: i32) -> Level {
+        Level { score: score, speed: 256, gap: gap, split_gap: split_gap, split_chance: 128, spacing: 180 }
+    }
+
+    #[test]
+    fn curve_fits_the_screen() {
+        for score in 0 .. CURVE[CURVE.len() - 1].score as u32 + 10 {
+            let level = level(score);
+            assert!(level.gap >= 1 && level.gap < WIDTH as i32);
+            assert!(level.split_gap >= 1 && level.split_gap < WIDTH as i32 / 2);
+        }
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = [point(0, 100, 60), point(10, 80, 40)];
+        assert_eq!(at(&curve, 5).gap, 90);
+        assert_eq!(at(&curve, 5).split_gap, 50);
+        assert_eq!(at(&curve, 20).gap, 80);
+    }
+
+    #[test]
+    fn clamps_gaps_wider_than_their_lanes() {
+        let curve = [point(0, 400, 200), point(10, 0, -10)];
+        assert_eq!(at(&curve, 0).gap, WIDTH as i32 - 1);
+        assert_eq!(at(&curve, 0).split_gap, WIDTH as i32 / 2 - 1);
+        assert_eq!(at(&curve, 10).gap, 1);
+        assert_eq!(at(&curve, 10).split_gap, 1);
+    }
+}
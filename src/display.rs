@@ -1,18 +1,35 @@
-use core::intrinsics::volatile_store;
-use collections::string::String;
-
+#[cfg(not(feature = "sim"))]
 use emlib::ebi;
+#[cfg(not(feature = "sim"))]
 use emlib::ebi::{TFTInit};
 
+#[cfg(not(feature = "sim"))]
 use emdrv::tft;
 
+#[cfg(not(feature = "sim"))]
 use cmsis::nvic;
 
+#[cfg(not(feature = "sim"))]
 use utils;
 
-use {Circle, Obstacle};
+#[cfg(not(feature = "sim"))]
+use core::intrinsics::{volatile_load, volatile_store};
+
+use core::prelude::*;
+use core::cmp;
+
+use {Circle, Obstacle, Rectangle};
+use background::Background;
 
-use font16x28::FONT_16X28;
+use framebuffer::FrameBuffer;
+use draw;
+use particles::MAX_PARTICLES;
+use text;
+use text::Style;
+use color::Rgb565;
+use theme::Theme;
+
+use font::FONT_16X28;
 
 pub const WIDTH: usize = 320;
 pub const HEIGHT: usize = 240;
@@ -23,8 +40,10 @@ pub const V_HEIGHT: usize = 240;
 
 pub const CIRCLE_SAMPLES: usize = 4 + 33 * 4;
 
-pub const CIRCLE_OFFSETS: [i32; CIRCLE_SAMPLES] = [-24,-696,-1368,-2040,-2712,-3383,-4055,-4727,-5399,-6070,-6742,-7413,-8085,-8756,-9428,-10099,-10770,-11441,-12112,-12783,-13454,-13453,-14124,-14123,-14794,-14793,-15464,-15463,-15462,-15461,-16132,-16131,-16130,-16129,-16128,-16127,-16126,-16125,-16124,-15451,-15450,-15449,-15448,-14775,-14774,-14101,-14100,-13427,-13426,-12753,-12080,-11407,-10734,-10061,-9388,-8716,-8043,-7371,-6698,-6026,-5353,-4681,-4009,-3337,-2664,-1992,-1320,-648,24,696,1368,2040,2712,3383,4055,4727,5399,6070,6742,7413,8085,8756,9428,10099,10770,11441,12112,12783,13454,13453,14124,14123,14794,14793,15464,15463,15462,15461,16132,16131,16130,16129,16128,16127,16126,16125,16124,15451,15450,15449,15448,14775,14774,14101,14100,13427,13426,12753,12080,11407,10734,10061,9388,8716,8043,7371,6698,6026,5353,4681,4009,3337,2664,1992,1320,648];
+// (x, y) offsets of the circle outline relative to its center
+pub const CIRCLE_POINTS: [(i32, i32); CIRCLE_SAMPLES] = [(-24,0),(-24,-1),(-24,-2),(-24,-3),(-24,-4),(-23,-5),(-23,-6),(-23,-7),(-23,-8),(-22,-9),(-22,-10),(-21,-11),(-21,-12),(-20,-13),(-20,-14),(-19,-15),(-18,-16),(-17,-17),(-16,-18),(-15,-19),(-14,-20),(-13,-20),(-12,-21),(-11,-21),(-10,-22),(-9,-22),(-8,-23),(-7,-23),(-6,-23),(-5,-23),(-4,-24),(-3,-24),(-2,-24),(-1,-24),(0,-24),(1,-24),(2,-24),(3,-24),(4,-24),(5,-23),(6,-23),(7,-23),(8,-23),(9,-22),(10,-22),(11,-21),(12,-21),(13,-20),(14,-20),(15,-19),(16,-18),(17,-17),(18,-16),(19,-15),(20,-14),(20,-13),(21,-12),(21,-11),(22,-10),(22,-9),(23,-8),(23,-7),(23,-6),(23,-5),(24,-4),(24,-3),(24,-2),(24,-1),(24,0),(24,1),(24,2),(24,3),(24,4),(23,5),(23,6),(23,7),(23,8),(22,9),(22,10),(21,11),(21,12),(20,13),(20,14),(19,15),(18,16),(17,17),(16,18),(15,19),(14,20),(13,20),(12,21),(11,21),(10,22),(9,22),(8,23),(7,23),(6,23),(5,23),(4,24),(3,24),(2,24),(1,24),(0,24),(-1,24),(-2,24),(-3,24),(-4,24),(-5,23),(-6,23),(-7,23),(-8,23),(-9,22),(-10,22),(-11,21),(-12,21),(-13,20),(-14,20),(-15,19),(-16,18),(-17,17),(-18,16),(-19,15),(-20,14),(-20,13),(-21,12),(-21,11),(-22,10),(-22,9),(-23,8),(-23,7),(-23,6),(-23,5),(-24,4),(-24,3),(-24,2),(-24,1)];
 
+#[cfg(not(feature = "sim"))]
 pub static TFT_INIT: TFTInit = TFTInit {
     bank:            ebi::TFTBank::_2,
     width:           ebi::TFTWidth::HalfWord,
@@ -55,73 +74,12 @@ pub static TFT_INIT: TFTInit = TFTInit {
 };
 
 
-static NUMBERS: [[[bool; 3]; 5]; 10] = [[
-    [true, true, true],
-    [true, false, true],
-    [true, false, true],
-    [true, false, true],
-    [true, true, true],
-],[
-    [false, false, true],
-    [false, false, true],
-    [false, false, true],
-    [false, false, true],
-    [false, false, true],
-],[
-    [true, true, true],
-    [false, false, true],
-    [true, true, true],
-    [true, false, false],
-    [true, true, true],
-],[
-    [true, true, true],
-    [false, false, true],
-    [true, true, true],
-    [false, false, true],
-    [true, true, true],
-],[
-    [true, false, true],
-    [true, false, true],
-    [true, true, true],
-    [false, false, true],
-    [false, false, true],
-],[
-    [true, true, true],
-    [true, false, false],
-    [true, true, true],
-    [false, false, true],
-    [true, true, true],
-],[
-    [true, true, true],
-    [true, false, false],
-    [true, true, true],
-    [true, false, true],
-    [true, true, true],
-],[
-    [true, true, true],
-    [false, false, true],
-    [false, true, true],
-    [false, true, false],
-    [false, true, false],
-],[
-    [true, true, true],
-    [true, false, true],
-    [true, true, true],
-    [true, false, true],
-    [true, true, true],
-],[
-    [true, true, true],
-    [true, false, true],
-    [true, true, true],
-    [false, false, true],
-    [false, false, true],
-],];
-
-
+#[cfg(not(feature = "sim"))]
 pub fn init() -> bool {
     tft::direct_init(&TFT_INIT)
 }
 
+#[cfg(not(feature = "sim"))]
 pub fn irq_enable(flags: u32) {
     ebi::int_disable(ebi::IF_MASK);
     ebi::int_clear(ebi::IF_MASK);
@@ -131,10 +89,50 @@ pub fn irq_enable(flags: u32) {
     nvic::enable_irq(nvic::IRQn::EBI);
 }
 
-// Keep track of horizontal offset
+// How far the background has scrolled, which grows by one every refresh
+#[cfg(not(feature = "sim"))]
 static mut hz_offset: u32 = 0;
+#[cfg(not(feature = "sim"))]
 static mut frame_ctr: u32 = 0;
 
+// The page of the double buffer on screen, and the page to show from the next frame
+#[cfg(not(feature = "sim"))]
+static mut shown_page: u32 = 0;
+#[cfg(not(feature = "sim"))]
+static mut next_page: u32 = 0;
+
+// How far every line of each page is moved to the left while it is shown
+#[cfg(not(feature = "sim"))]
+static mut line_offsets: [[i16; HEIGHT]; 2] = [[0; HEIGHT]; 2];
+
+/// The offset the background scrolls to, see `background`.
+#[cfg(not(feature = "sim"))]
+pub fn scroll_offset() -> u32 {
+    unsafe { volatile_load(&hz_offset as *const u32) }
+}
+
+/// Sets how far every line of a page is moved to the left, in pixels, while
+/// the page is shown. The offsets come from `raster::Effects`.
+#[cfg(not(feature = "sim"))]
+pub fn set_line_offsets<O: Fn(usize) -> i32>(page: usize, offset: O) {
+    for line in 0 .. HEIGHT {
+        unsafe {
+            volatile_store(&mut line_offsets[page][line] as *mut i16, offset(line) as i16);
+        }
+    }
+}
+
+/// Shows a page of the double buffer from the next frame on, and waits until
+/// the panel has started scanning it.
+#[cfg(not(feature = "sim"))]
+pub fn show_page(page: usize) {
+    unsafe {
+        volatile_store(&mut next_page as *mut u32, page as u32);
+        while volatile_load(&shown_page as *const u32) != page as u32 {}
+    }
+}
+
+#[cfg(not(feature = "sim"))]
 #[no_mangle]
 #[allow(non_snake_case)]
 pub unsafe extern fn EBI_IRQHandler() {
@@ -146,14 +144,10 @@ pub unsafe extern fn EBI_IRQHandler() {
         // Keep track of number of frames drawn
         frame_ctr += 1;
 
-        // Increase this increment to 2/4/8 to increase scroll speed
-        hz_offset += 1;
+        // Switch pages between two frames, so every frame shows a single page
+        shown_page = next_page;
 
-        // TODO: Not sure if this if-statement is required or not. What does it do?
-        // Wrap around when a full screen has been displayed
-        // if (hz_offset == (D_WIDTH + font16x28.c_width)) {
-        //     hz_offset = 0;
-        // }
+        hz_offset = hz_offset.wrapping_add(1);
     }
 
     // Process horizontal sync interrupt
@@ -165,7 +159,11 @@ pub unsafe extern fn EBI_IRQHandler() {
             line_number -= 3;
         }
 
-        ebi::tft_frame_base_set(line_number * V_WIDTH as u32 * 2);
+        let mut base = (line_number * V_WIDTH as u32 + shown_page * WIDTH as u32) as i32;
+        if (line_number as usize) < HEIGHT {
+            base += line_offsets[shown_page as usize][line_number as usize] as i32;
+        }
+        ebi::tft_frame_base_set(cmp::max(base, 0) as u32 * 2);
     }
 }
 
@@ -189,148 +187,358 @@ impl BufferLen for u64 {
     fn buffer_len() -> usize { (V_WIDTH * V_HEIGHT / 4) as usize }
 }
 
-macro_rules! frame_buffer {
-    () => (ebi::bank_address(ebi::BANK2) as *mut u16)
+pub fn clear<F: FrameBuffer>(fb: &mut F, color: Rgb565) {
+    let screen = Rectangle::new(0, 0, fb.width() as i32, fb.height() as i32);
+    draw::fill_rect(fb, &screen, color.0);
 }
 
-macro_rules! set {
-    ($fb:ident, $i:expr, $val:expr) => {
-        unsafe {
-            volatile_store($fb.offset($i as isize), $val);
-        }
+#[derive(Copy, Clone, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// How a number is laid out in a field of characters.
+#[derive(Copy, Clone)]
+pub struct NumberFormat {
+    /// Width of the field in characters.
+    pub digits: usize,
+    /// Where the number goes in the field, if it is shorter.
+    pub align: Align,
+    /// Fill the field up with leading zeros instead of spaces.
+    pub zeros: bool,
+}
+
+// Digits of the largest u32
+const MAX_DIGITS: usize = 10;
+
+/// Draws a number in a field starting at `(x, y)`, and returns the bounds of
+/// the field. A number with more digits than the field is drawn in full.
+pub fn draw_number<F: FrameBuffer>(fb: &mut F, number: u32, x: i32, y: i32,
+                                   format: &NumberFormat, style: Style) -> Rectangle {
+    let len = count_digits(number);
+    let mut digits = [0u8; MAX_DIGITS];
+    let mut rest = number;
+    for i in (0 .. len).rev() {
+        digits[i] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+    }
+
+    let chars = cmp::max(format.digits, len);
+    let padding = chars - len;
+    let before = match format.align {
+        _ if format.zeros => padding,
+        Align::Left => 0,
+        Align::Right => padding,
+        Align::Center => padding / 2,
+    };
+
+    // Padding is drawn as well, so it erases whatever longer number was there
+    let mut right = x;
+    for i in 0 .. chars {
+        let c = if i < before {
+            if format.zeros { '0' } else { ' ' }
+        } else if i < before + len {
+            digits[i - before] as char
+        } else {
+            ' '
+        };
+        right += text::draw_char(fb, right, y, c, style);
     }
+
+    Rectangle::new(x, y, right - x, (style.font.height * style.scale) as i32)
 }
 
-pub fn clear() {
-    let fb = frame_buffer!();
-    // Clear entire display using 32-bit write operations.
-    for i in 0 .. V_WIDTH * V_HEIGHT {
-        set!(fb, i, 0);
+fn count_digits(number: u32) -> usize {
+    let mut len = 1;
+    let mut rest = number / 10;
+    while rest > 0 {
+        len += 1;
+        rest /= 10;
     }
+    len
 }
-pub fn draw_number(number: usize, mut pos: usize, color: u16) {
-    let mut current_score = number;
-    pos = pos + 16; // Start with the third position
-
-    let fb = frame_buffer!();
-    for _ in 0 .. 3 {
-        let num: usize = current_score % 10;
-        current_score = current_score / 10;
-        let mut yy: usize = 0;
-        for y in 0 .. 5 {
-            let mut xx: usize = 0;
-            for x in 0 .. 3 {
-                let c = if NUMBERS[num][y][x] { color } else { 0 };
-
-                set!(fb, pos+xx+yy, c);
-                xx += 1;
-                set!(fb, pos+xx+yy, c);
-                xx += 1;
-            }
-            yy += V_WIDTH as usize;
-            xx = 0;
-            for x in 0 .. 3 {
-                let c = if NUMBERS[num][y][x] { color } else { 0 };
-
-                set!(fb, pos+xx+yy, c);
-                xx += 1;
-                set!(fb, pos+xx+yy, c);
-                xx += 1;
-            }
-            yy += V_WIDTH as usize;
+
+// Most rectangles tracked in a frame before the whole screen counts as changed,
+// enough for every particle on top of the circles and the text
+const MAX_DIRTY: usize = 16 + MAX_PARTICLES;
+
+/// The parts of the screen that were drawn over during a frame.
+///
+/// Whatever erases or draws pixels marks their bounds, so that the things
+/// beneath them know they have to be drawn again. Everything else is left as
+/// it was in the last frame.
+pub struct Dirty {
+    rects: [Rectangle; MAX_DIRTY],
+    len: usize,
+    // Set once more rectangles were marked than fit
+    all: bool,
+}
+
+impl Dirty {
+    pub fn new() -> Dirty {
+        Dirty {
+            rects: [Rectangle::new(0, 0, 0, 0); MAX_DIRTY],
+            len: 0,
+            all: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.all = false;
+    }
+
+    pub fn mark(&mut self, rect: Rectangle) {
+        if self.all || self.rects[.. self.len].iter().any(|r| r.contains(&rect)) {
+            return;
+        }
+
+        if self.len == MAX_DIRTY {
+            self.all = true;
+        } else {
+            self.rects[self.len] = rect;
+            self.len += 1;
+        }
+    }
+
+    /// Marks the whole screen, e.g. after it was cleared.
+    pub fn mark_all(&mut self) {
+        self.all = true;
+    }
+
+    pub fn intersects(&self, rect: &Rectangle) -> bool {
+        self.all || self.rects[.. self.len].iter().any(|r| r.intersects(rect))
+    }
+
+    // Calls `f` with the start and end of every marked part of a line
+    fn spans<G: FnMut(usize, usize)>(&self, y: i32, mut f: G) {
+        if self.all {
+            f(0, WIDTH);
+            return;
+        }
+
+        let line = Rectangle::new(0, y, WIDTH as i32, 1);
+        for rect in self.rects[.. self.len].iter().filter(|r| r.intersects(&line)) {
+            f(cmp::max(rect.dx, 0) as usize, cmp::min(rect.dx + rect.width, WIDTH as i32) as usize);
         }
-        pos -= 8;
     }
 }
 
-#[inline(always)]
-pub fn clear_circle(circle: &Circle) {
-    let fb = frame_buffer!();
-    for i in 0 .. CIRCLE_SAMPLES {
-        let idx = (circle.center as i32 + CIRCLE_OFFSETS[i]) as usize;
-        if idx > 0 {
-            set!(fb, idx, 0);
+/// A number on screen that is only drawn again when it changes, or when
+/// something was drawn over it.
+///
+/// The field grows when a number does not fit, away from the side it is
+/// aligned to, and never shrinks again, so no digits are left behind.
+pub struct NumberField {
+    x: i32,
+    y: i32,
+    format: NumberFormat,
+    style: Style,
+    shown: Option<u32>,
+}
+
+impl NumberField {
+    pub fn new(x: i32, y: i32, format: NumberFormat, style: Style) -> NumberField {
+        NumberField {
+            x: x,
+            y: y,
+            format: format,
+            style: style,
+            shown: None,
+        }
+    }
+
+    /// Changes the colors or font, which shows from the next time the field is drawn.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// The bounds of the field.
+    pub fn rect(&self) -> Rectangle {
+        let width = self.format.digits * self.style.font.width * self.style.scale;
+        let height = self.style.font.height * self.style.scale;
+        Rectangle::new(self.x, self.y, width as i32, height as i32)
+    }
+
+    pub fn draw<F: FrameBuffer>(&mut self, fb: &mut F, dirty: &Dirty, number: u32) {
+        if self.shown != Some(number) || dirty.intersects(&self.rect()) {
+            self.redraw(fb, number);
+        }
+    }
+
+    /// Draws the number even if it is already on screen.
+    pub fn redraw<F: FrameBuffer>(&mut self, fb: &mut F, number: u32) {
+        let len = count_digits(number);
+        if len > self.format.digits {
+            let extra = ((len - self.format.digits) * self.style.font.width * self.style.scale) as i32;
+            self.x -= match self.format.align {
+                Align::Left => 0,
+                Align::Right => extra,
+                Align::Center => extra / 2,
+            };
+            self.format.digits = len;
         }
+
+        draw_number(fb, number, self.x, self.y, &self.format, self.style);
+        self.shown = Some(number);
     }
 }
 
+/// The part of the frame buffer the routines below draw into. Whatever lies
+/// outside of it is left out, so nothing drawn partly off screen spills into
+/// the next line or the hidden columns of the virtual display.
+pub fn clip_rect<F: FrameBuffer>(fb: &F) -> Rectangle {
+    Rectangle::new(0, 0, fb.width() as i32, fb.height() as i32)
+}
+
 #[inline(always)]
-pub fn draw_circle(circle: &Circle) {
-    let fb = frame_buffer!();
-    let mut color = circle.color;
-
-    for i in 0 .. CIRCLE_SAMPLES {
-        let idx = (circle.center as i32 + CIRCLE_OFFSETS[i]) as usize;
-        if idx > 0 {
-            set!(fb, idx, color);
-            color += 32;
+pub fn clear_circle<F: FrameBuffer>(fb: &mut F, center: (i32, i32), theme: &Theme, background: &Background) {
+    let clip = clip_rect(fb);
+    let (cx, cy) = center;
+
+    for &(x, y) in CIRCLE_POINTS.iter() {
+        let (x, y) = (cx + x, cy + y);
+        if clip.contains_point(x, y) {
+            let color = background.color(theme, x as usize, y as usize);
+            fb.set_pixel(x as usize, y as usize, color);
         }
     }
 }
 
 #[inline(always)]
-pub fn draw_obstacle(obstacle: &Obstacle) {
-    let fb = frame_buffer!();
-    for i in 0..WIDTH {
-        if obstacle.obstacle[i] {
-            set!(fb, obstacle.pos + i, 63488);
-
-            if obstacle.pos >= 600 {
-                set!(fb, obstacle.pos + i - 1 * V_WIDTH as usize, 57344);
-            }
-            if obstacle.pos >= 1200 {
-                set!(fb, obstacle.pos + i - 2 * V_WIDTH as usize, 64);
-            }
-            if obstacle.pos >= 2000 {
-                set!(fb, obstacle.pos + i - 3 * V_WIDTH as usize, 0);
-            }
+pub fn draw_circle<F: FrameBuffer>(fb: &mut F, circle: &Circle) {
+    let clip = clip_rect(fb);
+    let (cx, cy) = circle.center();
+
+    for (i, &(x, y)) in CIRCLE_POINTS.iter().enumerate() {
+        let (x, y) = (cx + x, cy + y);
+        if clip.contains_point(x, y) {
+            fb.set_pixel(x as usize, y as usize, circle.animation.color(i).0);
         }
+    }
+}
 
+pub fn draw_obstacles<F: FrameBuffer>(fb: &mut F, obstacles: &[Obstacle], theme: &Theme, background: &Background,
+                                      dirty: &mut Dirty) {
+    for obstacle in obstacles.iter() {
+        draw_obstacle(fb, obstacle, theme, background, dirty);
     }
 }
-pub fn draw_fps(fps: u32) {
 
-		//draw_number(fps as usize, 10 + 10 * V_WIDTH, 0xffff);
+#[inline(always)]
+pub fn draw_obstacle<F: FrameBuffer>(fb: &mut F, obstacle: &Obstacle, theme: &Theme, background: &Background,
+                                     dirty: &mut Dirty) {
+    let y = obstacle.pos;
+    let tail = theme.obstacles.len() as i32 - 1;
+
+    if obstacle.drawn == Some(y) {
+        // Only draw the parts of the lines that were drawn over
+        for (i, &color) in theme.obstacles.iter().enumerate() {
+            let line = y - i as i32;
+            dirty.spans(line, |from, to| draw_obstacle_span(fb, obstacle, line, from, to, color.0));
+        }
+        return;
+    }
+
+    // Erase the lines the obstacle has moved past since it was last drawn
+    let mut top = y - tail;
+    if let Some(last) = obstacle.drawn {
+        for line in last - tail .. y - tail {
+            erase_obstacle_line(fb, obstacle, line, theme, background);
+        }
+        top = cmp::min(top, last - tail);
+    }
 
-		let text = format!("{} fps ", fps);
-		draw_string(0, 10, text);
+    // Fade out the trailing lines
+    for (i, &color) in theme.obstacles.iter().enumerate() {
+        draw_obstacle_line(fb, obstacle, y - i as i32, color.0);
+    }
 
+    let lines = Rectangle::new(0, top, WIDTH as i32, y + 1 - top);
+    dirty.mark(lines.intersection(&clip_rect(fb)));
 }
 
-fn draw_string(mut x: usize, y: usize, text: String) {
-
-    for ch in text.chars() {
-        draw_font(x, y, ch);
-        x += 16;
+pub fn clear_obstacle<F: FrameBuffer>(fb: &mut F, obstacle: &Obstacle, theme: &Theme, background: &Background,
+                                      dirty: &mut Dirty) {
+    if let Some(y) = obstacle.drawn {
+        let top = y - (theme.obstacles.len() as i32 - 1);
+        for line in top .. y + 1 {
+            erase_obstacle_line(fb, obstacle, line, theme, background);
+        }
+        let lines = Rectangle::new(0, top, WIDTH as i32, y + 1 - top);
+        dirty.mark(lines.intersection(&clip_rect(fb)));
     }
 }
 
-fn draw_font(x: usize, y: usize, c: char) {
-    let fb = frame_buffer!();
-    let font = &FONT_16X28;
-    let font_offset = c as usize - 0x20;
-    let mut idx = x + (y*V_WIDTH);
+#[inline(always)]
+fn draw_obstacle_line<F: FrameBuffer>(fb: &mut F, obstacle: &Obstacle, y: i32, color: u16) {
+    draw_obstacle_span(fb, obstacle, y, 0, WIDTH, color);
+}
 
+// Draws the background over a line of an obstacle
+fn erase_obstacle_line<F: FrameBuffer>(fb: &mut F, obstacle: &Obstacle, y: i32, theme: &Theme,
+                                       background: &Background) {
+    if let Some((y, from, to)) = clip_span(&clip_rect(fb), y, 0, WIDTH) {
+        for i in from .. to {
+            if obstacle.obstacle[i] {
+                let color = background.color(theme, i, y);
+                fb.set_pixel(i, y, color);
+            }
+        }
+    }
+}
 
-    for j in 0..font.c_height {
+#[inline(always)]
+fn draw_obstacle_span<F: FrameBuffer>(fb: &mut F, obstacle: &Obstacle, y: i32, from: usize, to: usize, color: u16) {
+    if let Some((y, from, to)) = clip_span(&clip_rect(fb), y, from, to) {
+        for i in from .. to {
+            if obstacle.obstacle[i] {
+                fb.set_pixel(i, y, color);
+            }
+        }
+    }
+}
 
-        for i in 0..font.c_width {
+// The line and the columns of the part of a span within `clip`, if there is any
+fn clip_span(clip: &Rectangle, y: i32, from: usize, to: usize) -> Option<(usize, usize, usize)> {
+    let from = cmp::max(from as i32, clip.dx);
+    let to = cmp::min(to as i32, clip.dx + clip.width);
+    if y < clip.dy || y >= clip.dy + clip.height || from >= to {
+        None
+    } else {
+        Some((y as usize, from as usize, to as usize))
+    }
+}
 
-            let color = font.data[j * font.width + font_offset * font.c_width + i];
-            set!(fb, idx, color);
-            idx += 1;
-        }
+pub fn draw_fps<F: FrameBuffer>(fb: &mut F, theme: &Theme, fps: u32) {
+    text::draw_fmt(fb, 0, 10, format_args!("{} fps ", fps), theme.text_style());
+}
 
-        idx += V_WIDTH - font.c_width;
+/// Draws the frame rate like a `NumberField`, only when it changed or was drawn over.
+pub fn update_fps<F: FrameBuffer>(fb: &mut F, theme: &Theme, dirty: &Dirty, fps: u32, shown: &mut Option<u32>) {
+    // Room for "999 fps "
+    let rect = Rectangle::new(0, 10, 8 * FONT_16X28.width as i32, FONT_16X28.height as i32);
 
+    if *shown != Some(fps) || dirty.intersects(&rect) {
+        draw_fps(fb, theme, fps);
+        *shown = Some(fps);
     }
+}
 
+/// Draws the number of pixels written in the last frame below the frame rate.
+pub fn draw_pixel_count<F: FrameBuffer>(fb: &mut F, theme: &Theme, pixels: usize) {
+    text::draw_fmt(fb, 0, 40, format_args!("{} px   ", pixels), theme.text_style());
 }
 
-pub fn debug_count() {
+#[cfg(not(feature = "sim"))]
+pub fn debug_count<F: FrameBuffer>(fb: &mut F) {
+    let format = NumberFormat { digits: 3, align: Align::Right, zeros: true };
+    let style = ::theme::CLASSIC.score_style();
     let mut num = 999;
     loop {
-        draw_number(num, (250 + 10 * V_WIDTH) as usize, 0xffff);
+        draw_number(fb, num, 250, 10, &format, style);
         num = if num == 0 { 999 } else { num - 1 };
         utils::delay(10);
     }
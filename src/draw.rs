@@ -0,0 +1,378 @@
+// Drawing primitives for any frame buffer.
+//
+// Coordinates are signed and may lie partly or completely off screen. Every
+// shape is clipped against the visible area of the frame buffer, so only the
+// pixels within `width()` x `height()` are written.
+
+use core::prelude::*;
+use core::cmp;
+
+use Rectangle;
+use framebuffer::FrameBuffer;
+
+/// Sets a single pixel, if it is on screen.
+#[inline(always)]
+pub fn pixel<F: FrameBuffer>(fb: &mut F, x: i32, y: i32, color: u16) {
+    if x >= 0 && y >= 0 && x < fb.width() as i32 && y < fb.height() as i32 {
+        fb.set_pixel(x as usize, y as usize, color);
+    }
+}
+
+/// Draws the horizontal line from `x0` to `x1` on line `y`, both ends included.
+pub fn hline<F: FrameBuffer>(fb: &mut F, x0: i32, x1: i32, y: i32, color: u16) {
+    if y < 0 || y >= fb.height() as i32 {
+        return;
+    }
+
+    let from = cmp::max(cmp::min(x0, x1), 0);
+    let to = cmp::min(cmp::max(x0, x1), fb.width() as i32 - 1);
+    if from <= to {
+        let idx = y as usize * fb.stride() + from as usize;
+        fb.fill(idx, (to - from + 1) as usize, color);
+    }
+}
+
+/// Draws the vertical line from `y0` to `y1` in column `x`, both ends included.
+pub fn vline<F: FrameBuffer>(fb: &mut F, x: i32, y0: i32, y1: i32, color: u16) {
+    if x < 0 || x >= fb.width() as i32 {
+        return;
+    }
+
+    let from = cmp::max(cmp::min(y0, y1), 0);
+    let to = cmp::min(cmp::max(y0, y1), fb.height() as i32 - 1);
+    for y in from .. to + 1 {
+        fb.set_pixel(x as usize, y as usize, color);
+    }
+}
+
+/// Draws a line between two points with Bresenham's algorithm.
+pub fn line<F: FrameBuffer>(fb: &mut F, x0: i32, y0: i32, x1: i32, y1: i32, color: u16) {
+    if y0 == y1 {
+        return hline(fb, x0, x1, y0, color);
+    }
+    if x0 == x1 {
+        return vline(fb, x0, y0, y1, color);
+    }
+
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+
+    let (mut x, mut y) = (x0, y0);
+    let mut err = dx + dy;
+    loop {
+        pixel(fb, x, y, color);
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Draws the outline of a rectangle, one pixel wide and inside its bounds.
+pub fn rect<F: FrameBuffer>(fb: &mut F, rect: &Rectangle, color: u16) {
+    if rect.width <= 0 || rect.height <= 0 {
+        return;
+    }
+
+    let right = rect.dx + rect.width - 1;
+    let bottom = rect.dy + rect.height - 1;
+
+    // Edges that fall on each other are only drawn once
+    hline(fb, rect.dx, right, rect.dy, color);
+    if rect.height > 1 {
+        hline(fb, rect.dx, right, bottom, color);
+    }
+    if rect.height > 2 {
+        vline(fb, rect.dx, rect.dy + 1, bottom - 1, color);
+        if rect.width > 1 {
+            vline(fb, right, rect.dy + 1, bottom - 1, color);
+        }
+    }
+}
+
+pub fn fill_rect<F: FrameBuffer>(fb: &mut F, rect: &Rectangle, color: u16) {
+    if rect.width <= 0 {
+        return;
+    }
+
+    for y in rect.dy .. rect.dy + rect.height {
+        hline(fb, rect.dx, rect.dx + rect.width - 1, y, color);
+    }
+}
+
+/// Draws the outline of a circle with the midpoint algorithm.
+pub fn circle<F: FrameBuffer>(fb: &mut F, cx: i32, cy: i32, radius: i32, color: u16) {
+    if radius < 0 {
+        return;
+    }
+
+    let mut x = radius;
+    let mut y = 0;
+    let mut err = 1 - radius;
+
+    while x >= y {
+        // Each point stands for one in every octant, some of which coincide
+        // on the diagonals and axes
+        for &(px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)].iter() {
+            pixel(fb, cx + px, cy + py, color);
+        }
+
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+}
+
+/// Draws a filled circle with the midpoint algorithm, one line at a time.
+pub fn fill_circle<F: FrameBuffer>(fb: &mut F, cx: i32, cy: i32, radius: i32, color: u16) {
+    if radius < 0 {
+        return;
+    }
+
+    let mut x = radius;
+    let mut y = 0;
+    let mut err = 1 - radius;
+
+    while x >= y {
+        hline(fb, cx - x, cx + x, cy + y, color);
+        if y != 0 {
+            hline(fb, cx - x, cx + x, cy - y, color);
+        }
+
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            // The lines at the top and bottom are done when x changes, so
+            // each of them is only drawn once
+            if x >= y {
+                hline(fb, cx - (y - 1), cx + (y - 1), cy + x, color);
+                hline(fb, cx - (y - 1), cx + (y - 1), cy - x, color);
+            }
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use core::cmp;
+
+    use Rectangle;
+    use super::{circle, fill_circle, fill_rect, hline, line, rect, vline};
+    use framebuffer::{FrameBuffer, RamFrameBuffer};
+
+    // Value of the pixels nothing was drawn to, including the columns past the
+    // width of the frame buffer
+    const BLANK: u16 = 0x1234;
+
+    // Draws onto a blank frame buffer of the given size, with a few columns
+    // to spare past its width, and returns all of its pixels
+    fn draw<G: Fn(&mut RamFrameBuffer)>(width: usize, height: usize, f: G) -> Vec<u16> {
+        let mut buffer = vec![BLANK; (width + 4) * height];
+        f(&mut RamFrameBuffer::new(&mut buffer, width, height, width + 4));
+        buffer
+    }
+
+    // Counts the pixels written, to tell when a shape draws over itself
+    struct Counter<'a> {
+        fb: RamFrameBuffer<'a>,
+        written: usize,
+    }
+
+    impl<'a> FrameBuffer for Counter<'a> {
+        fn width(&self) -> usize { self.fb.width() }
+
+        fn height(&self) -> usize { self.fb.height() }
+
+        fn stride(&self) -> usize { self.fb.stride() }
+
+        fn write(&mut self, idx: usize, color: u16) {
+            self.written += 1;
+            self.fb.write(idx, color);
+        }
+
+        fn read(&self, idx: usize) -> u16 { self.fb.read(idx) }
+    }
+
+    // The ends of a line from the center into each of the eight octants
+    const OCTANTS: [(i32, i32); 8] = [(20, 7), (7, 20), (-7, 20), (-20, 7), (-20, -7), (-7, -20), (7, -20), (20, -7)];
+
+    // Checks that a shape drawn around a point near the edges of a small frame
+    // buffer comes out like the same part of it drawn well within a large one
+    fn check_clipped<G: Fn(&mut RamFrameBuffer, i32, i32)>(cx: i32, cy: i32, f: G) {
+        let whole = draw(64, 64, |fb| f(fb, 32, 32));
+        let clipped = draw(16, 12, |fb| f(fb, cx, cy));
+
+        for y in 0 .. 12 {
+            for x in 0 .. 20 {
+                let (wx, wy) = (x as i32 - cx + 32, y as i32 - cy + 32);
+                let expected = if x >= 16 || wx < 0 || wx >= 64 || wy < 0 || wy >= 64 {
+                    BLANK
+                } else {
+                    whole[wy as usize * 68 + wx as usize]
+                };
+                assert!(clipped[y * 20 + x] == expected, "pixel ({}, {}) of the shape at ({}, {})", x, y, cx, cy);
+            }
+        }
+    }
+
+    #[test]
+    fn circle_is_clipped_at_the_edges() {
+        for &(cx, cy) in [(0, 0), (15, 11), (-5, 6), (20, 6), (8, -9), (8, 20)].iter() {
+            check_clipped(cx, cy, |fb, x, y| circle(fb, x, y, 10, 0));
+        }
+    }
+
+    #[test]
+    fn fill_circle_is_clipped_at_the_edges() {
+        for &(cx, cy) in [(0, 0), (15, 11), (-5, 6), (20, 6), (8, -9), (8, 20)].iter() {
+            check_clipped(cx, cy, |fb, x, y| fill_circle(fb, x, y, 10, 0));
+        }
+    }
+
+    #[test]
+    fn circle_off_screen_draws_nothing() {
+        let buffer = draw(16, 12, |fb| {
+            circle(fb, -20, 6, 10, 0);
+            fill_circle(fb, 8, 40, 10, 0);
+        });
+        assert!(buffer.iter().all(|&pixel| pixel == BLANK));
+    }
+
+    #[test]
+    fn hline_takes_its_ends_in_any_order() {
+        let forward = draw(16, 4, |fb| hline(fb, 3, 9, 1, 0));
+        let reversed = draw(16, 4, |fb| hline(fb, 9, 3, 1, 0));
+        assert!(forward == reversed);
+        assert!((0 .. 20).all(|x| (forward[20 + x] == 0) == (x >= 3 && x <= 9)));
+
+        // Clipped to the screen on both sides
+        let clipped = draw(16, 4, |fb| hline(fb, 30, -5, 2, 0));
+        assert!((0 .. 20).all(|x| (clipped[40 + x] == 0) == (x < 16)));
+        assert!(clipped.iter().filter(|&&pixel| pixel == 0).count() == 16);
+    }
+
+    #[test]
+    fn hline_off_screen_draws_nothing() {
+        let buffer = draw(16, 4, |fb| {
+            hline(fb, 0, 15, -1, 0);
+            hline(fb, 0, 15, 4, 0);
+            hline(fb, -9, -1, 0, 0);
+            hline(fb, 20, 16, 0, 0);
+        });
+        assert!(buffer.iter().all(|&pixel| pixel == BLANK));
+    }
+
+    #[test]
+    fn vline_takes_its_ends_in_any_order() {
+        let forward = draw(4, 12, |fb| vline(fb, 1, 3, 9, 0));
+        let reversed = draw(4, 12, |fb| vline(fb, 1, 9, 3, 0));
+        assert!(forward == reversed);
+        assert!((0 .. 12).all(|y| (forward[y * 8 + 1] == 0) == (y >= 3 && y <= 9)));
+
+        let clipped = draw(4, 12, |fb| vline(fb, 2, 30, -5, 0));
+        assert!(clipped.iter().filter(|&&pixel| pixel == 0).count() == 12);
+    }
+
+    #[test]
+    fn line_stays_on_the_ideal_line_in_every_octant() {
+        for &(dx, dy) in OCTANTS.iter() {
+            let buffer = draw(64, 64, |fb| line(fb, 32, 32, 32 + dx, 32 + dy, 0));
+            let major = cmp::max(dx.abs(), dy.abs());
+
+            let mut steps = vec![0; major as usize + 1];
+            for y in 0 .. 64 {
+                for x in 0 .. 68 {
+                    if buffer[y * 68 + x] != 0 {
+                        continue;
+                    }
+                    let (px, py) = (x as i32 - 32, y as i32 - 32);
+                    // Between the ends, and at most half a pixel off the line
+                    assert!(px * dx >= 0 && py * dy >= 0 && px.abs() <= dx.abs() && py.abs() <= dy.abs());
+                    assert!(2 * (py * dx - px * dy).abs() <= major, "({}, {}) on the line to ({}, {})", px, py, dx, dy);
+                    let step = if dx.abs() > dy.abs() { px.abs() } else { py.abs() };
+                    steps[step as usize] += 1;
+                }
+            }
+            // A single pixel for every step along the longer axis, ends included
+            assert!(steps.iter().all(|&pixels| pixels == 1), "line to ({}, {})", dx, dy);
+        }
+    }
+
+    #[test]
+    fn line_is_clipped_at_the_edges() {
+        for &(dx, dy) in OCTANTS.iter() {
+            for &(cx, cy) in [(0, 0), (15, 11), (-5, 6), (20, 6), (8, -9), (8, 20)].iter() {
+                check_clipped(cx, cy, |fb, x, y| line(fb, x - dx, y - dy, x + dx, y + dy, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn rect_draws_every_pixel_of_its_outline_once() {
+        for &(width, height) in [(5, 1), (5, 2), (1, 4), (2, 2), (1, 1), (5, 4)].iter() {
+            let mut buffer = vec![BLANK; 8 * 6];
+            let written = {
+                let mut fb = Counter { fb: RamFrameBuffer::new(&mut buffer, 8, 6, 8), written: 0 };
+                rect(&mut fb, &Rectangle::new(1, 1, width, height), 0);
+                fb.written
+            };
+
+            for y in 0 .. 6 {
+                for x in 0 .. 8 {
+                    let inside = x >= 1 && x <= width && y >= 1 && y <= height;
+                    let edge = x == 1 || x == width || y == 1 || y == height;
+                    assert!((buffer[y as usize * 8 + x as usize] == 0) == (inside && edge),
+                            "pixel ({}, {}) of a {}x{} rectangle", x, y, width, height);
+                }
+            }
+            assert!(written == buffer.iter().filter(|&&pixel| pixel == 0).count(),
+                    "{}x{} rectangle", width, height);
+        }
+    }
+
+    #[test]
+    fn rect_is_clipped_at_the_edges() {
+        for &(cx, cy) in [(0, 0), (15, 11), (-5, 6), (20, 6), (8, -9), (8, 20)].iter() {
+            check_clipped(cx, cy, |fb, x, y| rect(fb, &Rectangle::new(x - 9, y - 7, 19, 15), 0));
+        }
+    }
+
+    #[test]
+    fn fill_rect_is_clipped_at_the_edges() {
+        for &(cx, cy) in [(0, 0), (15, 11), (-5, 6), (20, 6), (8, -9), (8, 20)].iter() {
+            check_clipped(cx, cy, |fb, x, y| fill_rect(fb, &Rectangle::new(x - 9, y - 7, 19, 15), 0));
+        }
+    }
+
+    #[test]
+    fn empty_or_off_screen_rect_draws_nothing() {
+        let buffer = draw(16, 12, |fb| {
+            fill_rect(fb, &Rectangle::new(2, 2, 0, 5), 0);
+            fill_rect(fb, &Rectangle::new(2, 2, 5, -1), 0);
+            fill_rect(fb, &Rectangle::new(-9, 2, 9, 5), 0);
+            fill_rect(fb, &Rectangle::new(2, 12, 5, 5), 0);
+            rect(fb, &Rectangle::new(2, 2, 5, 0), 0);
+            rect(fb, &Rectangle::new(16, 2, 5, 5), 0);
+            rect(fb, &Rectangle::new(2, -5, 5, 5), 0);
+        });
+        assert!(buffer.iter().all(|&pixel| pixel == BLANK));
+    }
+}
@@ -0,0 +1,347 @@
+// Access to flash memory set aside for storing data across power cycles.
+//
+// Flash is erased a page at a time, which sets every bit of it, and is then
+// programmed a word at a time, which can only clear bits. The storage area is
+// addressed by byte offsets from its start, which are always word aligned.
+
+#[cfg(not(feature = "sim"))]
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+#[cfg(feature = "sim")]
+use collections::vec::Vec;
+
+// Value of an erased word
+pub const ERASED: u32 = 0xffffffff;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlashError {
+    Locked,
+    InvalidAddress,
+    Timeout,
+    // The power went away before the operation was done
+    PowerLoss,
+}
+
+pub trait Flash {
+    /// Size of an erasable page in bytes.
+    fn page_size(&self) -> usize;
+
+    /// Number of pages in the storage area.
+    fn pages(&self) -> usize;
+
+    fn read_word(&self, offset: usize) -> u32;
+
+    /// Programs a word, clearing the bits that are clear in `word`.
+    fn write_word(&mut self, offset: usize, word: u32) -> Result<(), FlashError>;
+
+    /// Sets every bit of a page.
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError>;
+}
+
+// Memory System Controller registers
+#[cfg(not(feature = "sim"))]
+const MSC_BASE: u32 = 0x400c0000;
+#[cfg(not(feature = "sim"))]
+const MSC_WRITECTRL: *mut u32 = (MSC_BASE + 0x008) as *mut u32;
+#[cfg(not(feature = "sim"))]
+const MSC_WRITECMD: *mut u32 = (MSC_BASE + 0x00c) as *mut u32;
+#[cfg(not(feature = "sim"))]
+const MSC_ADDRB: *mut u32 = (MSC_BASE + 0x010) as *mut u32;
+#[cfg(not(feature = "sim"))]
+const MSC_WDATA: *mut u32 = (MSC_BASE + 0x018) as *mut u32;
+#[cfg(not(feature = "sim"))]
+const MSC_STATUS: *mut u32 = (MSC_BASE + 0x01c) as *mut u32;
+#[cfg(not(feature = "sim"))]
+const MSC_LOCK: *mut u32 = (MSC_BASE + 0x03c) as *mut u32;
+
+#[cfg(not(feature = "sim"))]
+const WRITECTRL_WREN: u32 = 1 << 0;
+#[cfg(not(feature = "sim"))]
+const WRITECMD_LADDRIM: u32 = 1 << 0;
+#[cfg(not(feature = "sim"))]
+const WRITECMD_ERASEPAGE: u32 = 1 << 1;
+#[cfg(not(feature = "sim"))]
+const WRITECMD_WRITEONCE: u32 = 1 << 3;
+#[cfg(not(feature = "sim"))]
+const STATUS_BUSY: u32 = 1 << 0;
+#[cfg(not(feature = "sim"))]
+const STATUS_LOCKED: u32 = 1 << 1;
+#[cfg(not(feature = "sim"))]
+const STATUS_INVADDR: u32 = 1 << 2;
+#[cfg(not(feature = "sim"))]
+const STATUS_WDATAREADY: u32 = 1 << 3;
+#[cfg(not(feature = "sim"))]
+const LOCK_UNLOCK: u32 = 0x1b71;
+
+#[cfg(not(feature = "sim"))]
+pub const MSC_PAGE_SIZE: usize = 4096;
+
+// Polls of the status register before an operation is given up
+#[cfg(not(feature = "sim"))]
+const MSC_TIMEOUT: u32 = 1000000;
+
+/// The last pages of the on-chip flash of the EFM32GG990F1024.
+///
+/// The linker script leaves these pages out of the program. They are in the
+/// second flash bank, which keeps the code in the first bank readable while
+/// they are written.
+#[cfg(not(feature = "sim"))]
+pub struct MscFlash {
+    base: u32,
+    pages: usize,
+}
+
+#[cfg(not(feature = "sim"))]
+impl MscFlash {
+    pub fn new() -> MscFlash {
+        MscFlash {
+            base: 0x100000 - 2 * MSC_PAGE_SIZE as u32,
+            pages: 2,
+        }
+    }
+
+    // Loads the address to write to or erase, and starts the command. The word
+    // of a write is only stored once the controller is ready for it, after the
+    // address has been loaded.
+    fn command(&mut self, offset: usize, command: u32, word: Option<u32>) -> Result<(), FlashError> {
+        unsafe {
+            volatile_store(MSC_ADDRB, self.base + offset as u32);
+            volatile_store(MSC_WRITECMD, WRITECMD_LADDRIM);
+
+            let status = volatile_load(MSC_STATUS);
+            if status & STATUS_INVADDR != 0 {
+                return Err(FlashError::InvalidAddress);
+            }
+            if status & STATUS_LOCKED != 0 {
+                return Err(FlashError::Locked);
+            }
+
+            if let Some(word) = word {
+                try!(wait_for(STATUS_WDATAREADY, STATUS_WDATAREADY));
+                volatile_store(MSC_WDATA, word);
+            }
+            volatile_store(MSC_WRITECMD, command);
+        }
+
+        wait_for(STATUS_BUSY, 0)
+    }
+
+    // Runs a flash operation with writes to the flash enabled
+    fn unlocked<T, F: FnOnce(&mut MscFlash) -> T>(&mut self, f: F) -> T {
+        unsafe {
+            volatile_store(MSC_LOCK, LOCK_UNLOCK);
+            volatile_store(MSC_WRITECTRL, volatile_load(MSC_WRITECTRL) | WRITECTRL_WREN);
+        }
+
+        let result = f(self);
+
+        unsafe {
+            volatile_store(MSC_WRITECTRL, volatile_load(MSC_WRITECTRL) & !WRITECTRL_WREN);
+            volatile_store(MSC_LOCK, 0);
+        }
+        result
+    }
+}
+
+#[cfg(not(feature = "sim"))]
+fn wait_for(mask: u32, value: u32) -> Result<(), FlashError> {
+    for _ in 0 .. MSC_TIMEOUT {
+        if unsafe { volatile_load(MSC_STATUS) } & mask == value {
+            return Ok(());
+        }
+    }
+    Err(FlashError::Timeout)
+}
+
+#[cfg(not(feature = "sim"))]
+impl Flash for MscFlash {
+    fn page_size(&self) -> usize { MSC_PAGE_SIZE }
+
+    fn pages(&self) -> usize { self.pages }
+
+    fn read_word(&self, offset: usize) -> u32 {
+        unsafe { volatile_load((self.base + offset as u32) as *const u32) }
+    }
+
+    fn write_word(&mut self, offset: usize, word: u32) -> Result<(), FlashError> {
+        assert!(offset % 4 == 0 && offset < self.pages * MSC_PAGE_SIZE);
+
+        self.unlocked(|flash| flash.command(offset, WRITECMD_WRITEONCE, Some(word)))
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError> {
+        assert!(page < self.pages);
+
+        self.unlocked(|flash| flash.command(page * MSC_PAGE_SIZE, WRITECMD_ERASEPAGE, None))
+    }
+}
+
+/// Flash kept in RAM, for running the game on the host.
+///
+/// Power loss can be simulated by cutting the power after a given number of
+/// operations. The operation the power is cut in is left half done, and every
+/// operation after it fails.
+#[cfg(feature = "sim")]
+pub struct MemoryFlash {
+    words: Vec<u32>,
+    page_size: usize,
+    // Operations left until the power is cut
+    power: Option<usize>,
+}
+
+#[cfg(feature = "sim")]
+impl MemoryFlash {
+    /// Flash of `pages` erased pages of `page_size` bytes each.
+    pub fn new(pages: usize, page_size: usize) -> MemoryFlash {
+        assert!(page_size % 4 == 0);
+
+        MemoryFlash {
+            words: vec![ERASED; pages * page_size / 4],
+            page_size: page_size,
+            power: None,
+        }
+    }
+
+    /// Flash with the contents of an earlier `image`, where missing bytes are erased.
+    pub fn from_image(image: &[u8], pages: usize, page_size: usize) -> MemoryFlash {
+        let mut flash = MemoryFlash::new(pages, page_size);
+        for (i, word) in flash.words.iter_mut().enumerate() {
+            for j in 0 .. 4 {
+                if let Some(&b) = image.get(4 * i + j) {
+                    *word &= !(0xff << (8 * j)) | (b as u32) << (8 * j);
+                }
+            }
+        }
+        flash
+    }
+
+    /// The contents of the flash, with the words stored little endian.
+    pub fn image(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(4 * self.words.len());
+        for &word in self.words.iter() {
+            for j in 0 .. 4 {
+                image.push((word >> (8 * j)) as u8);
+            }
+        }
+        image
+    }
+
+    /// Cuts the power in the middle of the `operations`th operation from now.
+    pub fn cut_power_after(&mut self, operations: usize) {
+        self.power = Some(operations);
+    }
+
+    pub fn has_power(&self) -> bool {
+        self.power != Some(0)
+    }
+
+    // Counts down an operation, and tells if the power is cut during it
+    fn power_lost(&mut self) -> Result<bool, FlashError> {
+        match self.power {
+            Some(0) => Err(FlashError::PowerLoss),
+            Some(n) => {
+                self.power = Some(n - 1);
+                Ok(n == 1)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "sim")]
+impl Flash for MemoryFlash {
+    fn page_size(&self) -> usize { self.page_size }
+
+    fn pages(&self) -> usize { self.words.len() * 4 / self.page_size }
+
+    fn read_word(&self, offset: usize) -> u32 {
+        self.words[offset / 4]
+    }
+
+    fn write_word(&mut self, offset: usize, word: u32) -> Result<(), FlashError> {
+        if offset % 4 != 0 || offset / 4 >= self.words.len() {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        if try!(self.power_lost()) {
+            // Only the lower half of the word made it
+            self.words[offset / 4] &= word | 0xffff0000;
+            return Err(FlashError::PowerLoss);
+        }
+        self.words[offset / 4] &= word;
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError> {
+        if page >= self.pages() {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        let words = self.page_size / 4;
+        let start = page * words;
+        let len = if try!(self.power_lost()) { words / 2 } else { words };
+        for word in self.words[start .. start + len].iter_mut() {
+            *word = ERASED;
+        }
+
+        if len < words {
+            Err(FlashError::PowerLoss)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{Flash, FlashError, MemoryFlash, ERASED};
+
+    #[test]
+    fn starts_out_erased() {
+        let flash = MemoryFlash::new(2, 64);
+        assert_eq!(flash.pages(), 2);
+        assert!((0 .. 32).all(|i| flash.read_word(4 * i) == ERASED));
+        assert!(MemoryFlash::from_image(&[], 2, 64).image().iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn writes_only_clear_bits() {
+        let mut flash = MemoryFlash::new(2, 64);
+        flash.write_word(4, 0xff00ff00).unwrap();
+        flash.write_word(4, 0x0ff00ff0).unwrap();
+        assert_eq!(flash.read_word(4), 0x0f000f00);
+
+        flash.erase_page(0).unwrap();
+        assert_eq!(flash.read_word(4), ERASED);
+    }
+
+    #[test]
+    fn image_round_trips() {
+        let mut flash = MemoryFlash::new(2, 64);
+        flash.write_word(68, 0x12345678).unwrap();
+
+        let copy = MemoryFlash::from_image(&flash.image(), 2, 64);
+        assert_eq!(copy.read_word(68), 0x12345678);
+        assert_eq!(copy.read_word(64), ERASED);
+    }
+
+    #[test]
+    fn power_loss_leaves_operations_half_done() {
+        let mut flash = MemoryFlash::new(2, 64);
+        flash.cut_power_after(1);
+        assert_eq!(flash.write_word(0, 0), Err(FlashError::PowerLoss));
+        assert_eq!(flash.read_word(0), 0xffff0000);
+        assert!(!flash.has_power());
+        assert_eq!(flash.write_word(4, 0), Err(FlashError::PowerLoss));
+        assert_eq!(flash.read_word(4), ERASED);
+
+        let mut flash = MemoryFlash::new(2, 64);
+        for i in 0 .. 16 {
+            flash.write_word(4 * i, 0).unwrap();
+        }
+        flash.cut_power_after(1);
+        assert_eq!(flash.erase_page(0), Err(FlashError::PowerLoss));
+        assert!((0 .. 8).all(|i| flash.read_word(4 * i) == ERASED));
+        assert!((8 .. 16).all(|i| flash.read_word(4 * i) == 0));
+    }
+}
@@ -0,0 +1,63 @@
+// Bitmap fonts, one bit per pixel.
+//
+// The fonts are converted from the BDF files in `assets/fonts` by the build
+// script, which names each one after its file, so `font_16x28.bdf` becomes
+// `FONT_16X28`. The bitmap of a glyph covers only its inked pixels, row by row,
+// with every row padded to a whole byte and the leftmost pixel in the highest
+// bit.
+
+use core::prelude::*;
+
+pub struct Font {
+    /// The widest advance of the glyphs.
+    pub width: usize,
+    /// The height of a line of text.
+    pub height: usize,
+    /// The glyphs, ordered by their characters.
+    pub glyphs: &'static [Glyph],
+    /// Index of the glyph drawn for characters the font has no glyph for.
+    pub default: usize,
+    pub bitmap: &'static [u8],
+}
+
+pub struct Glyph {
+    pub code: u32,
+    /// How far the next glyph is to the right.
+    pub advance: u8,
+    // Position and size of the bitmap, from the top left of the line
+    pub left: i8,
+    pub top: i8,
+    pub width: u8,
+    pub height: u8,
+    /// Index of the first row in `Font::bitmap`.
+    pub offset: u32,
+}
+
+impl Font {
+    /// The glyph for a character, or the default glyph if there is none.
+    pub fn glyph(&self, c: char) -> &Glyph {
+        match self.glyphs.binary_search_by(|glyph| glyph.code.cmp(&(c as u32))) {
+            Ok(i) => &self.glyphs[i],
+            Err(_) => &self.glyphs[self.default],
+        }
+    }
+
+    /// Whether the pixel at `(x, y)` from the top left of the line is set.
+    pub fn pixel(&self, glyph: &Glyph, x: i32, y: i32) -> bool {
+        let (col, row) = (x - glyph.left as i32, y - glyph.top as i32);
+        if col < 0 || row < 0 || col >= glyph.width as i32 || row >= glyph.height as i32 {
+            return false;
+        }
+
+        let stride = (glyph.width as usize + 7) / 8;
+        let byte = self.bitmap[glyph.offset as usize + row as usize * stride + col as usize / 8];
+        byte & (0x80 >> (col % 8)) != 0
+    }
+
+    /// The width of a string in pixels.
+    pub fn width(&self, text: &str) -> usize {
+        text.chars().fold(0, |width, c| width + self.glyph(c).advance as usize)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/fonts.rs"));
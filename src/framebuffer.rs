@@ -0,0 +1,459 @@
+#[cfg(not(feature = "sim"))]
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+use core::u16;
+use collections::vec::Vec;
+
+#[cfg(not(feature = "sim"))]
+use emlib::ebi;
+
+#[cfg(not(feature = "sim"))]
+use display::{WIDTH, HEIGHT, V_WIDTH};
+
+/// A target the display routines can render RGB565 pixels into.
+///
+/// Pixels are addressed either by `(x, y)` or by a linear index into a buffer
+/// where each line is `stride()` pixels long.
+pub trait FrameBuffer {
+    /// Number of visible columns.
+    fn width(&self) -> usize;
+
+    /// Number of visible lines.
+    fn height(&self) -> usize;
+
+    /// Number of pixels between the start of two consecutive lines.
+    fn stride(&self) -> usize;
+
+    /// Writes a single pixel at the given linear index.
+    fn write(&mut self, idx: usize, color: u16);
+
+    /// Reads back a single pixel at the given linear index.
+    fn read(&self, idx: usize) -> u16;
+
+    /// Fills `len` consecutive pixels starting at the given linear index.
+    fn fill(&mut self, idx: usize, len: usize, color: u16) {
+        for i in idx .. idx + len {
+            self.write(i, color);
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
+        let idx = y * self.stride() + x;
+        self.write(idx, color);
+    }
+}
+
+/// The TFT frame buffer in EBI bank 2, driven directly by the EBI controller.
+#[cfg(not(feature = "sim"))]
+pub struct EbiFrameBuffer {
+    base: *mut u16,
+}
+
+#[cfg(not(feature = "sim"))]
+impl EbiFrameBuffer {
+    pub fn new() -> EbiFrameBuffer {
+        EbiFrameBuffer {
+            base: ebi::bank_address(ebi::BANK2) as *mut u16,
+        }
+    }
+}
+
+#[cfg(not(feature = "sim"))]
+impl FrameBuffer for EbiFrameBuffer {
+    fn width(&self) -> usize { WIDTH }
+
+    fn height(&self) -> usize { HEIGHT }
+
+    fn stride(&self) -> usize { V_WIDTH }
+
+    #[inline(always)]
+    fn write(&mut self, idx: usize, color: u16) {
+        unsafe {
+            volatile_store(self.base.offset(idx as isize), color);
+        }
+    }
+
+    fn read(&self, idx: usize) -> u16 {
+        unsafe {
+            volatile_load(self.base.offset(idx as isize))
+        }
+    }
+}
+
+/// A frame buffer backed by a plain slice of RAM, e.g. a `Vec<u16>`.
+pub struct RamFrameBuffer<'a> {
+    buffer: &'a mut [u16],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a> RamFrameBuffer<'a> {
+    pub fn new(buffer: &'a mut [u16], width: usize, height: usize, stride: usize) -> RamFrameBuffer<'a> {
+        assert!(width <= stride && buffer.len() >= stride * height);
+
+        RamFrameBuffer {
+            buffer: buffer,
+            width: width,
+            height: height,
+            stride: stride,
+        }
+    }
+
+    pub fn buffer(&self) -> &[u16] {
+        &*self.buffer
+    }
+}
+
+impl<'a> FrameBuffer for RamFrameBuffer<'a> {
+    fn width(&self) -> usize { self.width }
+
+    fn height(&self) -> usize { self.height }
+
+    fn stride(&self) -> usize { self.stride }
+
+    #[inline(always)]
+    fn write(&mut self, idx: usize, color: u16) {
+        self.buffer[idx] = color;
+    }
+
+    fn read(&self, idx: usize) -> u16 {
+        self.buffer[idx]
+    }
+
+    fn fill(&mut self, idx: usize, len: usize, color: u16) {
+        for pixel in self.buffer[idx .. idx + len].iter_mut() {
+            *pixel = color;
+        }
+    }
+}
+
+// Most fills remembered for repeating them on the other page. A round with four
+// circles draws up to about 1700 fills a frame. At 8 bytes a fill the log takes
+// 16 KB of the 128 KB of RAM, which leaves room for the 16 KB of the recording
+// in `main.rs`, the rest of the heap and the stack.
+const LOG_LEN: usize = 2048;
+
+// A run of pixels with the same color
+#[derive(Copy, Clone)]
+struct Fill {
+    idx: u32,
+    len: u16,
+    color: u16,
+}
+
+/// Two pages side by side within the lines of a frame buffer, one shown while
+/// the other one is drawn.
+///
+/// Everything is drawn to the back page. Once a frame is done, the back page is
+/// shown and the pages swap with `flip`. The game only draws what changed since
+/// the last frame, so `flip` repeats the drawing of the last frame on the new
+/// back page to bring it up to date.
+pub struct DoubleBuffer<F> {
+    fb: F,
+    // The page being drawn, 0 for the left one and 1 for the right one
+    back: usize,
+    // The drawing done since the last flip
+    log: Vec<Fill>,
+    // Set when there was too much drawing to remember
+    overflowed: bool,
+    // Pixels drawn since the last flip, and during the frame before it
+    written: usize,
+    last_written: usize,
+}
+
+impl<F: FrameBuffer> DoubleBuffer<F> {
+    pub fn new(fb: F) -> DoubleBuffer<F> {
+        assert!(2 * fb.width() <= fb.stride());
+
+        DoubleBuffer {
+            fb: fb,
+            back: 1,
+            log: Vec::with_capacity(LOG_LEN),
+            overflowed: false,
+            written: 0,
+            last_written: 0,
+        }
+    }
+
+    /// The page being drawn, which is shown next.
+    pub fn back_page(&self) -> usize {
+        self.back
+    }
+
+    /// The number of pixels drawn during the last frame, not counting the ones
+    /// `flip` repeats on the other page.
+    pub fn pixels_written(&self) -> usize {
+        self.last_written
+    }
+
+    /// Starts drawing to the other page, once the back page is shown.
+    pub fn flip(&mut self) {
+        self.back = 1 - self.back;
+        let offset = self.page_offset(self.back);
+
+        if self.overflowed {
+            // Copy the whole page that is now shown
+            let front = self.page_offset(1 - self.back);
+            for y in 0 .. self.fb.height() {
+                for x in 0 .. self.fb.width() {
+                    let idx = y * self.fb.stride() + x;
+                    let color = self.fb.read(front + idx);
+                    self.fb.write(offset + idx, color);
+                }
+            }
+        } else {
+            for fill in self.log.iter() {
+                self.fb.fill(offset + fill.idx as usize, fill.len as usize, fill.color);
+            }
+        }
+
+        self.log.clear();
+        self.overflowed = false;
+        self.last_written = self.written;
+        self.written = 0;
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.fb
+    }
+
+    // Index of the first pixel of a page
+    fn page_offset(&self, page: usize) -> usize {
+        page * self.fb.width()
+    }
+
+    fn remember(&mut self, idx: usize, len: usize, color: u16) {
+        if self.overflowed {
+            return;
+        }
+
+        // Extend the last fill if the pixels follow it
+        if let Some(last) = self.log.last_mut() {
+            let end = last.idx as usize + last.len as usize;
+            if last.color == color && end == idx && last.len as usize + len <= u16::MAX as usize {
+                last.len += len as u16;
+                return;
+            }
+        }
+
+        // Runs too long for a single fill are left to a copy of the whole page
+        if self.log.len() == LOG_LEN || len > u16::MAX as usize {
+            self.overflowed = true;
+        } else {
+            self.log.push(Fill { idx: idx as u32, len: len as u16, color: color });
+        }
+    }
+}
+
+impl<F: FrameBuffer> FrameBuffer for DoubleBuffer<F> {
+    fn width(&self) -> usize { self.fb.width() }
+
+    fn height(&self) -> usize { self.fb.height() }
+
+    fn stride(&self) -> usize { self.fb.stride() }
+
+    #[inline(always)]
+    fn write(&mut self, idx: usize, color: u16) {
+        let offset = self.page_offset(self.back);
+        self.fb.write(offset + idx, color);
+        self.remember(idx, 1, color);
+        self.written += 1;
+    }
+
+    fn read(&self, idx: usize) -> u16 {
+        self.fb.read(self.page_offset(self.back) + idx)
+    }
+
+    fn fill(&mut self, idx: usize, len: usize, color: u16) {
+        let offset = self.page_offset(self.back);
+        self.fb.fill(offset + idx, len, color);
+        self.remember(idx, len, color);
+        self.written += len;
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use core::u16;
+
+    use super::{DoubleBuffer, FrameBuffer, RamFrameBuffer, LOG_LEN};
+
+    const WIDTH: usize = 128;
+    const HEIGHT: usize = 64;
+    const STRIDE: usize = 2 * WIDTH;
+    // Enough lines for a fill longer than the longest one remembered
+    const TALL: usize = 600;
+
+    // Two pages that start out different, so it shows what `flip` copies
+    fn pages(height: usize) -> Vec<u16> {
+        let mut buffer = vec![0; STRIDE * height];
+        for y in 0 .. height {
+            for x in 0 .. WIDTH {
+                buffer[y * STRIDE + x] = 1;
+                buffer[y * STRIDE + WIDTH + x] = 2;
+            }
+        }
+        buffer
+    }
+
+    fn page(buffer: &[u16], page: usize) -> Vec<u16> {
+        let mut pixels = Vec::new();
+        for y in 0 .. buffer.len() / STRIDE {
+            for x in 0 .. WIDTH {
+                pixels.push(buffer[y * STRIDE + page * WIDTH + x]);
+            }
+        }
+        pixels
+    }
+
+    // Leaves the drawing to the default methods of `FrameBuffer`, to compare
+    // the ones `RamFrameBuffer` has of its own with
+    struct Pixels<'a>(RamFrameBuffer<'a>);
+
+    impl<'a> FrameBuffer for Pixels<'a> {
+        fn width(&self) -> usize { self.0.width() }
+
+        fn height(&self) -> usize { self.0.height() }
+
+        fn stride(&self) -> usize { self.0.stride() }
+
+        fn write(&mut self, idx: usize, color: u16) { self.0.write(idx, color) }
+
+        fn read(&self, idx: usize) -> u16 { self.0.read(idx) }
+    }
+
+    #[test]
+    fn set_pixel_leaves_the_columns_past_the_width() {
+        let mut buffer = vec![0; 6 * 3];
+        {
+            let mut fb = RamFrameBuffer::new(&mut buffer, 4, 3, 6);
+            assert_eq!((fb.width(), fb.height(), fb.stride()), (4, 3, 6));
+            fb.set_pixel(0, 0, 1);
+            fb.set_pixel(3, 1, 2);
+            fb.set_pixel(2, 2, 3);
+            assert_eq!(fb.read(6 + 3), 2);
+            assert_eq!(fb.buffer()[2 * 6 + 2], 3);
+        }
+        assert_eq!(buffer, vec![1, 0, 0, 0, 0, 0,
+                                0, 0, 0, 2, 0, 0,
+                                0, 0, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fill_covers_the_pixels_from_its_index_on() {
+        let mut buffer = vec![0; 6 * 3];
+        RamFrameBuffer::new(&mut buffer, 4, 3, 6).fill(6 + 2, 7, 5);
+        assert_eq!(buffer, vec![0, 0, 0, 0, 0, 0,
+                                0, 0, 5, 5, 5, 5,
+                                5, 5, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fill_writes_what_writing_pixel_by_pixel_does() {
+        let mut filled = vec![0; 6 * 3];
+        let mut written = vec![0; 6 * 3];
+        for &(idx, len, color) in [(0, 1, 1), (4, 9, 2), (17, 1, 3), (7, 0, 4)].iter() {
+            RamFrameBuffer::new(&mut filled, 4, 3, 6).fill(idx, len, color);
+            Pixels(RamFrameBuffer::new(&mut written, 4, 3, 6)).fill(idx, len, color);
+            assert_eq!(filled, written);
+        }
+    }
+
+    #[test]
+    fn new_takes_a_buffer_of_just_the_lines() {
+        let mut buffer = vec![0; 4 * 3];
+        let fb = RamFrameBuffer::new(&mut buffer, 4, 3, 4);
+        assert_eq!(fb.buffer().len(), 12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_a_width_beyond_the_stride() {
+        let mut buffer = vec![0; 6 * 3];
+        RamFrameBuffer::new(&mut buffer, 7, 3, 6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_a_buffer_short_of_the_lines() {
+        let mut buffer = vec![0; 6 * 3 - 1];
+        RamFrameBuffer::new(&mut buffer, 4, 3, 6);
+    }
+
+    #[test]
+    fn flip_repeats_the_drawing_on_the_other_page() {
+        let mut buffer = pages(HEIGHT);
+        {
+            let mut fb = DoubleBuffer::new(RamFrameBuffer::new(&mut buffer, WIDTH, HEIGHT, STRIDE));
+            assert_eq!(fb.back_page(), 1);
+            fb.fill(3 * STRIDE + 10, 20, 7);
+            fb.set_pixel(5, 40, 8);
+            fb.flip();
+            assert_eq!(fb.back_page(), 0);
+            assert_eq!(fb.pixels_written(), 21);
+        }
+
+        let (front, back) = (page(&buffer, 1), page(&buffer, 0));
+        for (i, (&shown, &drawn)) in front.iter().zip(back.iter()).enumerate() {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            if y == 3 && x >= 10 && x < 30 {
+                assert!(shown == 7 && drawn == 7);
+            } else if (x, y) == (5, 40) {
+                assert!(shown == 8 && drawn == 8);
+            } else {
+                // Nothing else was drawn, so the rest of the pages is left alone
+                assert!(shown == 2 && drawn == 1);
+            }
+        }
+    }
+
+    #[test]
+    fn flip_copies_the_whole_page_after_too_much_drawing() {
+        let mut buffer = pages(HEIGHT);
+        {
+            let mut fb = DoubleBuffer::new(RamFrameBuffer::new(&mut buffer, WIDTH, HEIGHT, STRIDE));
+            // Pixels apart from each other, so none of them extends the fill before it
+            for i in 0 .. LOG_LEN + 1 {
+                let (x, y) = (2 * i % WIDTH, 2 * i / WIDTH);
+                fb.set_pixel(x, y, 3);
+            }
+            fb.flip();
+        }
+
+        assert!(page(&buffer, 0) == page(&buffer, 1));
+        assert!(page(&buffer, 0).iter().all(|&pixel| pixel == 2 || pixel == 3));
+    }
+
+    #[test]
+    fn remember_merges_fills_up_to_the_longest_one() {
+        let mut buffer = pages(TALL);
+        let mut fb = DoubleBuffer::new(RamFrameBuffer::new(&mut buffer, WIDTH, TALL, STRIDE));
+        let max = u16::MAX as usize;
+
+        fb.fill(0, max - 1, 4);
+        fb.fill(max - 1, 1, 4);
+        assert_eq!(fb.log.len(), 1);
+        assert_eq!(fb.log[0].len as usize, max);
+
+        // The next pixel doesn't fit in that fill, and starts another one
+        fb.fill(max, 1, 4);
+        assert_eq!(fb.log.len(), 2);
+        assert_eq!((fb.log[1].idx as usize, fb.log[1].len), (max, 1));
+        assert!(!fb.overflowed);
+    }
+
+    #[test]
+    fn flip_copies_the_whole_page_after_a_fill_too_long_to_remember() {
+        let mut buffer = pages(TALL);
+        {
+            let mut fb = DoubleBuffer::new(RamFrameBuffer::new(&mut buffer, WIDTH, TALL, STRIDE));
+            fb.fill(0, u16::MAX as usize + 1, 5);
+            assert!(fb.overflowed);
+            fb.flip();
+        }
+
+        assert!(page(&buffer, 0) == page(&buffer, 1));
+        assert!(page(&buffer, 0).iter().filter(|&&pixel| pixel == 5).count() > u16::MAX as usize / 2);
+    }
+}
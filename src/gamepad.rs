@@ -1,3 +1,8 @@
+use emlib::gpio;
+
+use controller::Controller;
+use state::Game;
+
 /*
 This approach makes button interrupts a lot faster, probably due to the way interrupts
 are handled by the 'gpioint' module. This way we ignore everything with functions specific for
@@ -35,3 +40,13 @@ pub fn init() {
         *gpio_ien = 0xff;
     }
 }
+
+/// The buttons of the external gameboard on Port C.
+pub struct Gamepad;
+
+impl Controller for Gamepad {
+    fn buttons(&mut self, _game: &Game) -> u32 {
+        // Read status of gpio pins, the circles beyond SW1-SW8 have no buttons
+        gpio::port_in_get(gpio::Port::C) | !0xff
+    }
+}
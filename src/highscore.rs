@@ -0,0 +1,325 @@
+// High score table, kept in flash across power cycles.
+//
+// The table is stored as an append-only log of records, each holding the whole
+// table. A new record is written to the first erased slot after the latest one,
+// and once a page is full the log moves on to the next page, so every page is
+// erased equally often. The latest record is the valid one with the highest
+// sequence number:
+//
+//   record:  sequence: u32 | scores: [u16; 10] | crc: u32
+//
+// all stored as little endian words, with the CRC-32 of the record written
+// last. A record that was cut short by a reset fails its CRC and is skipped,
+// and a page is only erased once the latest record is on another page, so
+// there is always an intact record to fall back on.
+
+use core::prelude::*;
+
+use flash::{Flash, FlashError, ERASED};
+
+pub const TABLE_LEN: usize = 10;
+
+const RECORD_WORDS: usize = 2 + TABLE_LEN / 2;
+const RECORD_LEN: usize = 4 * RECORD_WORDS;
+
+/// The best scores, from the highest to the lowest.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HighScores {
+    scores: [u16; TABLE_LEN],
+}
+
+impl HighScores {
+    pub fn new() -> HighScores {
+        HighScores { scores: [0; TABLE_LEN] }
+    }
+
+    pub fn scores(&self) -> &[u16] {
+        &self.scores
+    }
+
+    pub fn best(&self) -> u32 {
+        self.scores[0] as u32
+    }
+
+    /// Adds a score to the table, and returns its rank if it made it in.
+    pub fn insert(&mut self, score: u32) -> Option<usize> {
+        // Erased flash reads as 0xffff, which is never a score
+        let score = if score > 0xfffe { 0xfffe } else { score as u16 };
+
+        let rank = match self.scores.iter().position(|&s| score > s) {
+            Some(rank) => rank,
+            None => return None,
+        };
+
+        let mut i = TABLE_LEN - 1;
+        while i > rank {
+            self.scores[i] = self.scores[i - 1];
+            i -= 1;
+        }
+        self.scores[rank] = score;
+
+        Some(rank)
+    }
+}
+
+/// The high score log in a storage area of at least two pages.
+pub struct ScoreStore<F> {
+    flash: F,
+    // The latest record, with its sequence number
+    latest: Option<(u32, HighScores)>,
+    // Page of the latest record
+    page: usize,
+    // Offset of the slot to write the next record to, if the page has room
+    next: Option<usize>,
+}
+
+impl<F: Flash> ScoreStore<F> {
+    /// Finds the latest record in the flash.
+    pub fn open(flash: F) -> ScoreStore<F> {
+        assert!(flash.pages() >= 2);
+
+        let slots = flash.page_size() / RECORD_LEN;
+        let mut latest = None;
+        let mut page = 0;
+        let mut slot = 0;
+
+        for p in 0 .. flash.pages() {
+            for s in 0 .. slots {
+                let offset = p * flash.page_size() + s * RECORD_LEN;
+                if let Some((sequence, scores)) = read_record(&flash, offset) {
+                    let newer = match latest {
+                        Some((latest_sequence, _)) => sequence > latest_sequence,
+                        None => true,
+                    };
+                    if newer {
+                        latest = Some((sequence, scores));
+                        page = p;
+                        slot = s + 1;
+                    }
+                }
+            }
+        }
+
+        let mut store = ScoreStore {
+            flash: flash,
+            latest: latest,
+            page: page,
+            next: None,
+        };
+        store.next = store.find_slot(page, slot);
+        store
+    }
+
+    /// The latest table that was saved, if any.
+    pub fn scores(&self) -> Option<HighScores> {
+        self.latest.map(|(_, scores)| scores)
+    }
+
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    pub fn flash_mut(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Appends a record with the given table.
+    pub fn save(&mut self, scores: &HighScores) -> Result<(), FlashError> {
+        let page_size = self.flash.page_size();
+
+        let offset = match self.next {
+            Some(offset) => offset,
+            None => {
+                // Move on to the next page
+                let page = (self.page + 1) % self.flash.pages();
+                try!(self.flash.erase_page(page));
+                self.page = page;
+                page * page_size
+            },
+        };
+
+        // Never write to a slot twice, even if this write does not make it
+        let page = self.page;
+        self.next = self.find_slot(page, (offset % page_size) / RECORD_LEN + 1);
+
+        let sequence = match self.latest {
+            Some((sequence, _)) => sequence + 1,
+            None => 0,
+        };
+
+        let words = record_words(sequence, scores);
+        for (i, &word) in words.iter().enumerate() {
+            try!(self.flash.write_word(offset + 4 * i, word));
+        }
+
+        self.latest = Some((sequence, *scores));
+        Ok(())
+    }
+
+    // The first erased slot of a page, starting at `slot`
+    fn find_slot(&self, page: usize, slot: usize) -> Option<usize> {
+        let page_size = self.flash.page_size();
+        for s in slot .. page_size / RECORD_LEN {
+            let offset = page * page_size + s * RECORD_LEN;
+            if (0 .. RECORD_WORDS).all(|i| self.flash.read_word(offset + 4 * i) == ERASED) {
+                return Some(offset);
+            }
+        }
+        None
+    }
+}
+
+fn record_words(sequence: u32, scores: &HighScores) -> [u32; RECORD_WORDS] {
+    let mut words = [0; RECORD_WORDS];
+    words[0] = sequence;
+    for i in 0 .. TABLE_LEN / 2 {
+        words[1 + i] = scores.scores[2 * i] as u32 | (scores.scores[2 * i + 1] as u32) << 16;
+    }
+    words[RECORD_WORDS - 1] = crc32(&words[.. RECORD_WORDS - 1]);
+    words
+}
+
+fn read_record<F: Flash>(flash: &F, offset: usize) -> Option<(u32, HighScores)> {
+    let mut words = [0; RECORD_WORDS];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = flash.read_word(offset + 4 * i);
+    }
+
+    // An erased sequence number is never written
+    if words[0] == ERASED || crc32(&words[.. RECORD_WORDS - 1]) != words[RECORD_WORDS - 1] {
+        return None;
+    }
+
+    let mut scores = HighScores::new();
+    for i in 0 .. TABLE_LEN / 2 {
+        scores.scores[2 * i] = words[1 + i] as u16;
+        scores.scores[2 * i + 1] = (words[1 + i] >> 16) as u16;
+    }
+    Some((words[0], scores))
+}
+
+// CRC-32 (IEEE 802.3) of the words, taken as little endian bytes
+fn crc32(words: &[u32]) -> u32 {
+    let mut crc = 0xffffffff;
+    for &word in words.iter() {
+        for i in 0 .. 4 {
+            crc ^= (word >> (8 * i)) & 0xff;
+            for _ in 0 .. 8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{HighScores, ScoreStore, RECORD_LEN};
+    use flash::{Flash, FlashError, MemoryFlash};
+
+    // Three records to a page, so the log moves on to the next page quickly
+    const PAGE_SIZE: usize = 3 * RECORD_LEN;
+    const PAGES: usize = 2;
+
+    fn table(score: u32) -> HighScores {
+        let mut scores = HighScores::new();
+        scores.insert(score);
+        scores.insert(score / 2);
+        scores
+    }
+
+    // Opens the flash again, as after a reset
+    fn reopen(store: &ScoreStore<MemoryFlash>) -> ScoreStore<MemoryFlash> {
+        ScoreStore::open(MemoryFlash::from_image(&store.flash().image(), PAGES, PAGE_SIZE))
+    }
+
+    #[test]
+    fn empty_flash_has_no_scores() {
+        assert_eq!(ScoreStore::open(MemoryFlash::new(PAGES, PAGE_SIZE)).scores(), None);
+        assert_eq!(ScoreStore::open(MemoryFlash::from_image(&[], PAGES, PAGE_SIZE)).scores(), None);
+    }
+
+    #[test]
+    fn keeps_the_latest_table() {
+        let mut store = ScoreStore::open(MemoryFlash::new(PAGES, PAGE_SIZE));
+        store.save(&table(10)).unwrap();
+        store.save(&table(20)).unwrap();
+        assert_eq!(store.scores(), Some(table(20)));
+        assert_eq!(reopen(&store).scores(), Some(table(20)));
+    }
+
+    #[test]
+    fn moves_on_to_the_other_page_when_one_is_full() {
+        let mut store = ScoreStore::open(MemoryFlash::new(PAGES, PAGE_SIZE));
+        for i in 0 .. 4 * PAGES * 3 {
+            store.save(&table(i as u32 + 1)).unwrap();
+
+            // The page of the latest record is never erased, so the other one
+            // still holds the records from before the move
+            let page = (i / 3) % PAGES;
+            let other = (page + 1) % PAGES;
+            assert!(store.flash().read_word(page * PAGE_SIZE + (i % 3) * RECORD_LEN) == i as u32);
+            if i >= 3 {
+                assert!(store.flash().read_word(other * PAGE_SIZE + 2 * RECORD_LEN) == i as u32 - 1 - i as u32 % 3);
+            }
+
+            // Reopening finds the record, and carries on after it
+            store = reopen(&store);
+            assert_eq!(store.scores(), Some(table(i as u32 + 1)));
+        }
+    }
+
+    #[test]
+    fn skips_a_record_with_a_bad_crc() {
+        let mut store = ScoreStore::open(MemoryFlash::new(PAGES, PAGE_SIZE));
+        store.save(&table(10)).unwrap();
+        store.save(&table(20)).unwrap();
+
+        // Flip a bit of the CRC of the second record
+        let mut image = store.flash().image();
+        image[2 * RECORD_LEN - 1] ^= 0x01;
+        let mut store = ScoreStore::open(MemoryFlash::from_image(&image, PAGES, PAGE_SIZE));
+        assert_eq!(store.scores(), Some(table(10)));
+
+        // The broken slot is not written to again
+        store.save(&table(30)).unwrap();
+        assert_eq!(reopen(&store).scores(), Some(table(30)));
+        assert_eq!(store.flash().image()[.. 2 * RECORD_LEN], image[.. 2 * RECORD_LEN]);
+    }
+
+    #[test]
+    fn survives_power_loss_in_every_operation() {
+        // Every number of records saved before, so the power is cut in the
+        // writes of a slot as well as in the erase of a page
+        for saved in 0 .. 3 * PAGES + 1 {
+            let mut operation = 1;
+            loop {
+                let mut store = ScoreStore::open(MemoryFlash::new(PAGES, PAGE_SIZE));
+                for i in 0 .. saved {
+                    store.save(&table(i as u32 + 1)).unwrap();
+                }
+                let before = store.scores();
+
+                store.flash_mut().cut_power_after(operation);
+                let result = store.save(&table(100));
+                let after = reopen(&store).scores();
+
+                match result {
+                    Err(FlashError::PowerLoss) => assert_eq!(after, before),
+                    Ok(()) => {
+                        assert_eq!(after, Some(table(100)));
+                        break;
+                    },
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+
+                // Saving again after the reset never loses the table either
+                let mut store = reopen(&store);
+                store.save(&table(200)).unwrap();
+                assert_eq!(reopen(&store).scores(), Some(table(200)));
+
+                operation += 1;
+            }
+        }
+    }
+}
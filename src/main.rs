@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(feature = "sim"), no_std)]
 #![warn(warnings)]
 #![allow(unsigned_negation)]
 #![feature(lang_items, core, no_std, asm)]
@@ -11,42 +11,130 @@ extern crate rand;
 #[macro_use]
 extern crate collections;
 
+#[cfg(not(feature = "sim"))]
 extern crate cmsis;
+#[cfg(not(feature = "sim"))]
 extern crate emlib;
+#[cfg(not(feature = "sim"))]
 extern crate emdrv;
+#[cfg(not(feature = "sim"))]
 extern crate kits;
 
 use core::prelude::*;
+use core::cmp;
+
+use collections::vec::Vec;
 
 use rand::Rng;
 
+#[cfg(not(feature = "sim"))]
 use prand::PRandom;
-
+#[cfg(not(feature = "sim"))]
+use replay::{Recorder, Replay};
+#[cfg(not(feature = "sim"))]
+use state::Game;
+#[cfg(not(feature = "sim"))]
+use flash::MscFlash;
+#[cfg(not(feature = "sim"))]
+use highscore::{HighScores, ScoreStore};
+#[cfg(not(feature = "sim"))]
+use controller::{Controller, Input, Players, Script};
+#[cfg(not(feature = "sim"))]
+use gamepad::Gamepad;
+#[cfg(not(feature = "sim"))]
+use ai::Ai;
+#[cfg(not(feature = "sim"))]
+use screenshot::Uart;
+
+#[cfg(not(feature = "sim"))]
 use emlib::ebi;
+#[cfg(not(feature = "sim"))]
 use emlib::cmu;
+#[cfg(not(feature = "sim"))]
 use emlib::gpio;
 
+#[cfg(not(feature = "sim"))]
 use kits::dk::{bc, bsp};
 
-use display::CIRCLE_SAMPLES;
-
-const BENCHMARK_MODE: bool = true;
+use display::{Align, Dirty, NumberField, NumberFormat};
+use difficulty::Level;
+use physics::{Physics, Vector};
+use animation::Animation;
+use theme::Theme;
+use background::Background;
+use particles::Particles;
+
+use framebuffer::FrameBuffer;
+#[cfg(not(feature = "sim"))]
+use framebuffer::{DoubleBuffer, EbiFrameBuffer};
+
+// Number of circles on the hardware, controlled from SW1-SW4 and SW5-SW8
+#[cfg(not(feature = "sim"))]
+const PLAYERS: usize = 2;
+// Set to e.g. `Some(include_bytes!("../session.cgr"))` to replay a recorded session
+#[cfg(not(feature = "sim"))]
+const REPLAY_SESSION: Option<&'static [u8]> = None;
+// The recording a circle replays when it is set to replay at boot
+#[cfg(not(feature = "sim"))]
+static DEMO_SESSION: &'static [u8] = include_bytes!("../assets/demo.cgr");
+// The script a circle plays when it is set to script at boot, which sways from
+// side to side. Each circle only takes its own bits, so every step holds the
+// same button of all of them.
+#[cfg(not(feature = "sim"))]
+static DEMO_SCRIPT: [(u32, u32); 4] = [(!0x1111, 30), (!0x5555, 10), (!0x4444, 30), (!0x5555, 10)];
+// The recording of the current session can be dumped from RAM with a debugger
+#[cfg(not(feature = "sim"))]
+const RECORD_LEN: usize = 16 * 1024;
+// Shows the number of pixels written in each frame below the frame rate
+#[cfg(not(feature = "sim"))]
+const SHOW_PIXEL_COUNT: bool = false;
+#[cfg(not(feature = "sim"))]
 static mut LAST_FRAME_COUNT: u32 = 0;
+#[cfg(not(feature = "sim"))]
 static mut FRAME_COUNT: u32 = 0;
 
+#[cfg(not(feature = "sim"))]
 pub mod gamepad;
+#[cfg(not(feature = "sim"))]
 pub mod utils;
 pub mod display;
+pub mod draw;
+pub mod framebuffer;
 pub mod ai;
+pub mod controller;
+pub mod difficulty;
+pub mod flash;
+pub mod highscore;
+pub mod physics;
 pub mod prand;
-pub mod font16x28;
-
+pub mod sprite;
+pub mod text;
+pub mod color;
+pub mod theme;
+pub mod animation;
+pub mod replay;
+pub mod state;
+pub mod font;
+pub mod screenshot;
+pub mod raster;
+pub mod background;
+pub mod particles;
+pub mod transition;
+#[cfg(feature = "sim")]
+pub mod sim;
+
+#[cfg(not(feature = "sim"))]
 fn main() {
     bsp::init(bsp::EBI);
     init();
     run();
 }
 
+#[cfg(feature = "sim")]
+fn main() {
+    sim::main();
+}
+
 #[derive(Copy, Clone)]
 pub struct Rectangle {
     dx: i32,
@@ -55,29 +143,146 @@ pub struct Rectangle {
     height: i32,
 }
 
+impl Rectangle {
+    pub fn new(dx: i32, dy: i32, width: i32, height: i32) -> Rectangle {
+        Rectangle {
+            dx: dx,
+            dy: dy,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn intersects(&self, other: &Rectangle) -> bool {
+        self.dx < other.dx + other.width && other.dx < self.dx + self.width &&
+            self.dy < other.dy + other.height && other.dy < self.dy + self.height
+    }
+
+    pub fn contains(&self, other: &Rectangle) -> bool {
+        self.dx <= other.dx && other.dx + other.width <= self.dx + self.width &&
+            self.dy <= other.dy && other.dy + other.height <= self.dy + self.height
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.dx && x < self.dx + self.width && y >= self.dy && y < self.dy + self.height
+    }
+
+    /// The part of the rectangle that lies within `other`, which is empty if
+    /// they don't intersect.
+    pub fn intersection(&self, other: &Rectangle) -> Rectangle {
+        let left = cmp::max(self.dx, other.dx);
+        let top = cmp::max(self.dy, other.dy);
+        let right = cmp::min(self.dx + self.width, other.dx + other.width);
+        let bottom = cmp::min(self.dy + self.height, other.dy + other.height);
+        Rectangle::new(left, top, cmp::max(right - left, 0), cmp::max(bottom - top, 0))
+    }
+}
+
+// Most circles that fit on the screen at once
+pub const MAX_CIRCLES: usize = 4;
+
+// Where the centers of the circles start
+const CIRCLE_STARTS: [(i32, i32); MAX_CIRCLES] = [(100, 100), (200, 174), (100, 174), (200, 100)];
+
+/// The buttons moving a circle, as bits of the button state.
+#[derive(Copy, Clone)]
+pub struct Keys {
+    pub left: u32,
+    pub up: u32,
+    pub right: u32,
+    pub down: u32,
+}
+
+impl Keys {
+    /// Every circle gets four bits of its own, so the first two circles are
+    /// moved with SW1-SW4 and SW5-SW8 of the gamepad.
+    pub fn for_circle(index: usize) -> Keys {
+        let shift = 4 * index;
+        Keys {
+            left: 0x1 << shift,
+            up: 0x2 << shift,
+            right: 0x4 << shift,
+            down: 0x8 << shift,
+        }
+    }
+
+    pub fn mask(&self) -> u32 {
+        self.left | self.up | self.right | self.down
+    }
+}
+
 pub struct Circle {
+    // Bounds of the circle in whole pixels, following `position`
     rect: Rectangle,
-    center: i32,
-    color: u16,
+    // Top left corner of the bounds, in fixed-point
+    position: Vector,
+    velocity: Vector,
+    physics: Physics,
+    animation: Animation,
+    keys: Keys,
+}
+
+impl Circle {
+    fn new(index: usize, theme: &Theme) -> Circle {
+        let (x, y) = CIRCLE_STARTS[index];
+        Circle {
+            rect: Rectangle {
+                dx: x - 24,
+                dy: y - 24,
+                width: 51,
+                height: 51,
+            },
+            position: Vector::from_pixels(x - 24, y - 24),
+            velocity: Vector::new(0, 0),
+            physics: physics::DEFAULT,
+            animation: Animation::new(theme.circles[index]),
+            keys: Keys::for_circle(index),
+        }
+    }
+
+    /// The pixel the circle is drawn around.
+    pub fn center(&self) -> (i32, i32) {
+        let (x, y) = self.position.to_pixels();
+        (x + 24, y + 24)
+    }
+
+    fn set_position(&mut self, position: Vector) {
+        let (x, y) = position.to_pixels();
+        self.position = position;
+        self.rect.dx = x;
+        self.rect.dy = y;
+    }
 }
 
 pub struct Obstacle {
     rect: Rectangle,
     obstacle: [bool; display::WIDTH],
-    pos: usize,
+    // The leading line, which may lie above or below the screen
+    pos: i32,
+    // Position in 1/256 lines
+    y: i32,
+    // The line the obstacle was last drawn at
+    drawn: Option<i32>,
     gap1: (i32, i32),
     gap2: Option<(i32, i32)>,
 }
 
 pub struct GameEnv {
-    circle1: Circle,
-    circle2: Circle,
-    obstacle: Obstacle,
+    circles: Vec<Circle>,
+    // Obstacles on screen, from the lowest to the highest
+    obstacles: Vec<Obstacle>,
     score: u32,
     max_score: u32,
-    frame: i32,
+    // What changed on screen during the current frame
+    dirty: Dirty,
+    score_field: NumberField,
+    max_score_field: NumberField,
+    theme: &'static Theme,
+    background: Background,
+    particles: Particles,
 }
 
+#[cfg(not(feature = "sim"))]
 fn init() {
     // Configure for 48MHz HFXO operation of core clock
     cmu::clock_select_set(cmu::Clock::HF, cmu::Select::HFXO);
@@ -99,202 +304,389 @@ fn init() {
 
     ebi::tfth_stride_set((display::V_WIDTH - display::WIDTH) as u32 * 2);
     display::irq_enable(ebi::IF_VFPORCH | ebi::IF_HSYNC);
-    display::clear();
+    // Clear the entire virtual display, including both pages
+    EbiFrameBuffer::new().fill(0, display::V_WIDTH * display::V_HEIGHT, 0);
 
     gamepad::init();
+    screenshot::init();
 
 }
 
+#[cfg(not(feature = "sim"))]
 fn run() {
 
-    let mut random_number_generator = PRandom::new();
+    let mut fb = DoubleBuffer::new(EbiFrameBuffer::new());
+
+    match REPLAY_SESSION {
+        Some(data) => {
+            let mut replay = Replay::new(data).unwrap();
+            let random_number_generator = PRandom::with_state(replay.rng_state());
+            let players = replay.players();
+
+            // Replays start from the recorded high score, and never save theirs
+            let mut scores = HighScores::new();
+            scores.insert(replay.max_score());
+            play(&mut fb, &mut replay, random_number_generator, players, scores, None);
+        },
+        None => {
+            let store = ScoreStore::open(MscFlash::new());
+            let scores = store.scores().unwrap_or(HighScores::new());
+
+            // The buttons of each circle held during boot pick its controller
+            let held = !gpio::port_in_get(gpio::Port::C);
+            let mut controller = Players { controllers: Vec::new() };
+            for i in 0 .. PLAYERS {
+                controller.controllers.push(select_input(held, Keys::for_circle(i)));
+            }
+            play(&mut fb, &mut controller, PRandom::new(), PLAYERS, scores, Some(store));
+        },
+    }
+}
+
+// Left or right picks the gamepad, up the demo script and down the demo
+// recording, and a circle without any of them held is played by the AI
+#[cfg(not(feature = "sim"))]
+fn select_input(held: u32, keys: Keys) -> Input<'static> {
+    if held & (keys.left | keys.right) != 0 {
+        Input::Gamepad(Gamepad)
+    } else if held & keys.up != 0 {
+        Input::Script(Script::new(&DEMO_SCRIPT, true))
+    } else if held & keys.down != 0 {
+        Input::Replay(Replay::new(DEMO_SESSION).unwrap())
+    } else {
+        Input::Ai(Ai::new())
+    }
+}
 
-    let mut env: GameEnv = restart(0, &mut random_number_generator);
+#[cfg(not(feature = "sim"))]
+fn play<C: Controller>(fb: &mut DoubleBuffer<EbiFrameBuffer>, controller: &mut C, mut random_number_generator: PRandom,
+                       players: usize, scores: HighScores,
+                       mut store: Option<ScoreStore<MscFlash>>) -> ! {
+
+    let mut recorder = Recorder::new(random_number_generator.state(), players, scores.best(), RECORD_LEN);
+
+    let mut game = Game::new(fb, &mut random_number_generator, players, scores);
+    let mut saved = scores;
+    let mut shown_fps = None;
+    // Holding left and right of the second circle sends a screenshot
+    let screenshot_keys = Keys::for_circle(1).left | Keys::for_circle(1).right;
+    let mut screenshot_held = false;
 
     loop {
         // Clear any gpio interrupts
         let flags = gpio::int_get();
         gpio::int_clear(flags);
 
-        let old_rect1: Rectangle = env.circle1.rect;
-        display::clear_circle(&env.circle1);
+        let rng_state = random_number_generator.state();
+        let buttons = controller.buttons(&game);
 
-        let buttons = if BENCHMARK_MODE {
-            // Simulate buttons with AI
-            ai::get_simulate_buttons(&env)
-        } else {
-            // Read status of gpio pins
-            gpio::port_in_get(gpio::Port::C)
-        };
+        game.scroll(display::scroll_offset());
+        let restarted = game.update(fb, &mut random_number_generator, buttons);
 
-        if buttons & 0x1 == 0 && env.circle1.rect.dx > 0 {
-            env.circle1.center -= 1;
-            env.circle1.rect.dx -= 1;
+        recorder.record_frame(buttons, game.env());
+        if restarted {
+            recorder.record_restart(rng_state);
         }
-        if buttons & 0x2 == 0 && env.circle1.rect.dy > 0 {
-            env.circle1.center -= display::V_WIDTH as i32;
-            env.circle1.rect.dy -= 1;
-        }
-        if buttons & 0x4 == 0 && env.circle1.rect.dx < 268 {
-            env.circle1.center += 1;
-            env.circle1.rect.dx += 1;
-        }
-        if buttons & 0x8 == 0 && env.circle1.rect.dy < 189 {
-            env.circle1.center += display::V_WIDTH as i32;
-            env.circle1.rect.dy += 1;
+        controller.frame_done(game.env(), rng_state, restarted);
+
+        if *game.scores() != saved {
+            saved = *game.scores();
+            if let Some(ref mut store) = store {
+                // Failing to save only loses this score once the power is gone
+                let _ = store.save(&saved);
+            }
         }
 
-        let old_rect2: Rectangle = env.circle2.rect;
-        display::clear_circle(&env.circle2);
+        unsafe { FRAME_COUNT += 1; };
 
-        if buttons & 0x10 == 0 && env.circle2.rect.dx > 0 {
-            env.circle2.center -= 1;
-            env.circle2.rect.dx -= 1;
-        }
-        if buttons & 0x20 == 0 && env.circle2.rect.dy > 0 {
-            env.circle2.center -= display::V_WIDTH as i32;
-            env.circle2.rect.dy -= 1;
-        }
-        if buttons & 0x40 == 0 && env.circle2.rect.dx < 268 {
-            env.circle2.center += 1;
-            env.circle2.rect.dx += 1;
-        }
-        if buttons & 0x80 == 0 && env.circle2.rect.dy < 189 {
-            env.circle2.center += display::V_WIDTH as i32;
-            env.circle2.rect.dy += 1;
+        let env = game.env();
+        display::update_fps(fb, env.theme, &env.dirty, unsafe { LAST_FRAME_COUNT }, &mut shown_fps);
+        if SHOW_PIXEL_COUNT {
+            let pixels = fb.pixels_written();
+            display::draw_pixel_count(fb, env.theme, pixels);
         }
 
-        detect_circle_collision(&mut env, old_rect1, old_rect2);
+        let effects = game.effects();
+        display::set_line_offsets(fb.back_page(), |line| effects.offset(line));
+        display::show_page(fb.back_page());
 
-        if detect_collission(&env, env.circle1.rect) || detect_collission(&env, env.circle2.rect) {
-            env = restart(env.max_score, &mut random_number_generator);
-            continue;
+        // Read straight from the gamepad, so the AI playing the circle can't take one
+        let held = !gpio::port_in_get(gpio::Port::C) & screenshot_keys == screenshot_keys;
+        if held && !screenshot_held {
+            // The page just shown is still the one being drawn until the flip
+            screenshot::write_bmp(fb, &mut Uart);
         }
+        screenshot_held = held;
+
+        fb.flip();
+    }
+}
 
-        update_obstacle(&mut env, &mut random_number_generator);
+/// Advances the game by a single frame using the given button state.
+///
+/// Returns `false` if one of the circles hit the obstacle, in which case the
+/// obstacles stay where they were, the circle bursts into particles, which
+/// `settle` plays out, and only the circles and particles are drawn again.
+pub fn step<F: FrameBuffer, R: Rng>(fb: &mut F, env: &mut GameEnv, rng: &mut R, buttons: u32) -> bool {
+    // Everything drawn below goes over the pixels the particles leave
+    env.particles.erase(fb, env.theme, &env.background, &mut env.dirty);
 
-        display::draw_circle(&env.circle1);
-        increment_color(&mut env.circle1, 2000);
+    let mut old_positions = [Vector::new(0, 0); MAX_CIRCLES];
 
-        display::draw_circle(&env.circle2);
-        increment_color(&mut env.circle2, 12000);
+    for (i, circle) in env.circles.iter_mut().enumerate() {
+        old_positions[i] = circle.position;
+        move_circle(circle, buttons);
+    }
 
-        display::draw_number(env.score as usize, 250 + 10 * display::V_WIDTH, 0xffff);
-        display::draw_number(env.max_score as usize, 276 + 10 * display::V_WIDTH, 0x2ee0);
+    resolve_circle_collisions(&mut env.circles, &old_positions);
 
-        unsafe { FRAME_COUNT += 1; };
+    // Circles that stayed in place are simply drawn over
+    for (i, circle) in env.circles.iter().enumerate() {
+        let (x, y) = old_positions[i].to_pixels();
+        if (x, y) != circle.position.to_pixels() {
+            display::clear_circle(fb, (x + 24, y + 24), env.theme, &env.background);
+            env.dirty.mark(Rectangle::new(x, y, circle.rect.width, circle.rect.height));
+            if env.theme.trails {
+                env.particles.trail((x + 24, y + 24), circle.animation.color(0));
+            }
+        }
+    }
+
+    if let Some(i) = env.circles.iter().position(|circle| detect_collission(env, circle.rect)) {
+        {
+            let circle = &env.circles[i];
+            env.particles.burst(circle.center(), |point| circle.animation.color(point));
+        }
 
-        display::draw_fps(unsafe { LAST_FRAME_COUNT });
+        // The crash stays on screen, so what was erased above is drawn again
+        env.particles.draw(fb, env.theme);
+        for circle in env.circles.iter() {
+            display::draw_circle(fb, circle);
+            env.dirty.mark(circle.rect);
+        }
+        return false;
     }
-}
 
-fn restart<R: Rng>(max_score: u32, rng: &mut R) -> GameEnv {
-    display::clear();
+    update_obstacles(fb, env, rng);
 
-    let circle1 = Circle {
-        rect: Rectangle {
-            dx: 76,
-            dy: 76,
-            width: 51,
-            height: 51,
-        },
-        center: 100 * display::V_WIDTH as i32 + 100,
-        color: 2000,
-    };
+    env.particles.advance();
+    env.particles.draw(fb, env.theme);
 
+    for circle in env.circles.iter_mut() {
+        display::draw_circle(fb, circle);
+        env.dirty.mark(circle.rect);
+        circle.animation.advance();
+    }
 
-    let circle2 = Circle {
-        rect: Rectangle {
-            dx: 176,
-            dy: 150,
-            width: 51,
-            height: 51,
-        },
-        center: 174 * display::V_WIDTH as i32 + 200,
-        color: 12000,
-    };
+    env.score_field.draw(fb, &env.dirty, env.score);
+    env.max_score_field.draw(fb, &env.dirty, env.max_score);
 
-    let obstacle = generate_obstacle(rng);
+    true
+}
+
+/// Starts a new round with `players` circles, between 1 and `MAX_CIRCLES`,
+/// without drawing anything.
+pub fn restart<R: Rng>(players: usize, max_score: u32, theme: &'static Theme, rng: &mut R) -> GameEnv {
+    assert!(players >= 1 && players <= MAX_CIRCLES);
+
+    let circles = (0 .. players).map(|i| Circle::new(i, theme)).collect();
+
+    let mut obstacles = Vec::new();
+    obstacles.push(generate_obstacle(rng, &difficulty::level(0)));
+
+    // The score and the best score side by side, growing apart once they
+    // outgrow three digits
+    let score_format = NumberFormat { digits: 3, align: Align::Right, zeros: true };
+    let max_score_format = NumberFormat { align: Align::Left, .. score_format };
 
     GameEnv {
-        circle1: circle1,
-        circle2: circle2,
-        obstacle: obstacle,
+        circles: circles,
+        obstacles: obstacles,
         score: 0,
         max_score: max_score,
-        frame: 0,
+        dirty: Dirty::new(),
+        score_field: NumberField::new(250, 10, score_format, theme.score_style()),
+        max_score_field: NumberField::new(276, 10, max_score_format, theme.max_score_style()),
+        theme: theme,
+        background: Background::new(),
+        particles: Particles::new(),
     }
 }
 
-
-fn increment_color(circle: &mut Circle, default: u16) {
-    circle.color += 32;
-    if circle.color + 64 > default + CIRCLE_SAMPLES as u16 * 32 {
-        circle.color = default;
+/// Switches the colors of a round over to another theme, without drawing anything.
+pub fn set_theme(env: &mut GameEnv, theme: &'static Theme) {
+    for (i, circle) in env.circles.iter_mut().enumerate() {
+        circle.animation = Animation::new(theme.circles[i]);
     }
+    env.score_field.set_style(theme.score_style());
+    env.max_score_field.set_style(theme.max_score_style());
+    env.theme = theme;
 }
 
-fn detect_circle_collision(env: &mut GameEnv, old_rect1: Rectangle, old_rect2: Rectangle) {
-    let rect1 = &mut env.circle1.rect;
-    let rect2 = &mut env.circle2.rect;
-    let mut diff: i32 = (rect1.dx - rect2.dx) * (rect1.dx - rect2.dx) + (rect1.dy - rect2.dy) * (rect1.dy - rect2.dy);
+/// Draws the whole screen of a round again, e.g. after the theme changed.
+pub fn redraw<F: FrameBuffer>(fb: &mut F, env: &mut GameEnv) {
+    display::clear(fb, env.theme.background);
+    env.dirty.mark_all();
 
-    if diff < 2500 { // COLLISSION
-        let mut diff2 = (old_rect1.dx - rect2.dx) * (old_rect1.dx - rect2.dx) + (rect1.dy - rect2.dy) * (rect1.dy - rect2.dy);
+    env.background.draw(fb, env.theme);
+    display::draw_obstacles(fb, &env.obstacles, env.theme, &env.background, &mut env.dirty);
+    env.particles.draw(fb, env.theme);
+    for circle in env.circles.iter() {
+        display::draw_circle(fb, circle);
+    }
+    env.score_field.redraw(fb, env.score);
+    env.max_score_field.redraw(fb, env.max_score);
+}
 
-        if diff2 > diff { // undo x movement in circle1 if that improves it
-            assert!(rect1.dx - old_rect1.dx == 1 || rect1.dx - old_rect1.dx == -1);
-            env.circle1.center += (old_rect1.dx - rect1.dx) as i32;
-            rect1.dx = old_rect1.dx;
-            diff = diff2;
-        }
+/// Moves the particles of a round on by a frame while everything else stands
+/// still, e.g. after a circle hit an obstacle.
+pub fn settle<F: FrameBuffer>(fb: &mut F, env: &mut GameEnv) {
+    env.particles.erase(fb, env.theme, &env.background, &mut env.dirty);
+    env.particles.advance();
 
-        if diff < 2500 { // COLLISSION
-            diff2 = (rect1.dx - rect2.dx) * (rect1.dx - rect2.dx) + (old_rect1.dy - rect2.dy) * (old_rect1.dy - rect2.dy);
+    display::draw_obstacles(fb, &env.obstacles, env.theme, &env.background, &mut env.dirty);
+    env.particles.draw(fb, env.theme);
+    for circle in env.circles.iter() {
+        display::draw_circle(fb, circle);
+    }
+    env.score_field.draw(fb, &env.dirty, env.score);
+    env.max_score_field.draw(fb, &env.dirty, env.max_score);
+}
 
-            if diff2 > diff { // undo y movement in circle1
-                assert!(rect1.dy - old_rect1.dy == 1 || rect1.dy - old_rect1.dy == -1);
-                env.circle1.center += if (old_rect1.dy - rect1.dy) == 1 { display::V_WIDTH } else { -display::V_WIDTH } as i32;
-                rect1.dy = old_rect1.dy;
-                diff = diff2;
-            }
+// Furthest the top left corner of a circle can move, in pixels. Drawing is
+// clipped to the screen, so this only keeps the circles in the playing field.
+const MAX_X: i32 = 268;
+const MAX_Y: i32 = 189;
+
+fn move_circle(circle: &mut Circle, buttons: u32) {
+    let keys = circle.keys;
+    let pressed = |key: u32| if buttons & key == 0 { 1 } else { 0 };
+
+    let physics = circle.physics;
+    let velocity = Vector::new(
+        physics::accelerate(&physics, circle.velocity.x, pressed(keys.right) - pressed(keys.left)),
+        physics::accelerate(&physics, circle.velocity.y, pressed(keys.down) - pressed(keys.up)),
+    );
+    let mut position = Vector::new(circle.position.x + velocity.x, circle.position.y + velocity.y);
+    circle.velocity = velocity;
+
+    // Stop at the edges of the screen
+    let max = Vector::from_pixels(MAX_X, MAX_Y);
+    if position.x < 0 || position.x > max.x {
+        position.x = cmp::max(cmp::min(position.x, max.x), 0);
+        circle.velocity.x = 0;
+    }
+    if position.y < 0 || position.y > max.y {
+        position.y = cmp::max(cmp::min(position.y, max.y), 0);
+        circle.velocity.y = 0;
+    }
 
-            if diff < 2500 { // COLLISSION
-                diff2 = (rect1.dx - old_rect2.dx) * (rect1.dx - old_rect2.dx) + (rect1.dy - rect2.dy) * (rect1.dy - rect2.dy);
+    circle.set_position(position);
+}
 
-                if diff2 > diff { //  undo x movement in circle 2
-                    assert!(rect2.dx - old_rect2.dx == 1 || rect2.dx - old_rect2.dx == -1);
-                    env.circle2.center += (old_rect2.dx - rect2.dx) as i32;
-                    rect2.dx = old_rect2.dx;
-                    diff = diff2;
-                }
+// Squared distance between two circles below which they overlap
+const CIRCLE_COLLISION: i32 = 2500;
 
-                if diff < 2500 { // COLLISSION
-                    diff2 = (rect1.dx - rect2.dx) * (rect1.dx - rect2.dx) + (rect1.dy - old_rect2.dy) * (rect1.dy - old_rect2.dy);
+fn circle_distance(rect1: Rectangle, rect2: Rectangle) -> i32 {
+    (rect1.dx - rect2.dx) * (rect1.dx - rect2.dx) + (rect1.dy - rect2.dy) * (rect1.dy - rect2.dy)
+}
 
-                    if diff2 > diff {  // undo y movement in circle 2
-                        assert!(rect2.dy - old_rect2.dy == 1 || rect2.dy - old_rect2.dy == -1);
-                        env.circle2.center += if (old_rect2.dy - rect2.dy) == 1 { display::V_WIDTH } else { -display::V_WIDTH } as i32;
-                        rect2.dy = old_rect2.dy;
-                        diff = diff2;
-                    }
+fn resolve_circle_collisions(circles: &mut [Circle], old_positions: &[Vector]) {
+    // Moving two circles apart can push one of them into a third, so check all
+    // pairs again until none overlap. Circles only ever move back to where they
+    // were in the last frame, when nothing overlapped, so this always ends.
+    let mut collided = true;
+    while collided {
+        collided = false;
+        for j in 1 .. circles.len() {
+            let (before, after) = circles.split_at_mut(j);
+            for i in 0 .. j {
+                if detect_circle_collision(&mut before[i], old_positions[i], &mut after[0], old_positions[j]) {
+                    collided = true;
                 }
             }
         }
     }
+}
+
+// Moves two overlapping circles apart, by undoing their movements one axis at a
+// time. Returns `true` if the circles overlapped.
+fn detect_circle_collision(circle1: &mut Circle, old1: Vector, circle2: &mut Circle, old2: Vector) -> bool {
+    let mut diff = circle_distance(circle1.rect, circle2.rect);
+
+    if diff >= CIRCLE_COLLISION {
+        return false;
+    }
+
+    // undo x movement in circle1 if that improves it
+    let position = Vector::new(old1.x, circle1.position.y);
+    diff = undo_move(circle1, position, circle2.rect, diff);
+
+    if diff < CIRCLE_COLLISION { // undo y movement in circle1
+        let position = Vector::new(circle1.position.x, old1.y);
+        diff = undo_move(circle1, position, circle2.rect, diff);
+    }
+
+    if diff < CIRCLE_COLLISION { // undo x movement in circle 2
+        let position = Vector::new(old2.x, circle2.position.y);
+        diff = undo_move(circle2, position, circle1.rect, diff);
+    }
+
+    if diff < CIRCLE_COLLISION { // undo y movement in circle 2
+        let position = Vector::new(circle2.position.x, old2.y);
+        diff = undo_move(circle2, position, circle1.rect, diff);
+    }
+
+    if diff < CIRCLE_COLLISION {
+        // Undoing single movements was not enough, put both circles back
+        circle1.set_position(old1);
+        circle1.velocity = Vector::new(0, 0);
+        circle2.set_position(old2);
+        circle2.velocity = Vector::new(0, 0);
+        diff = circle_distance(circle1.rect, circle2.rect);
+    }
 
-    assert!(diff >= 2500);
+    assert!(diff >= CIRCLE_COLLISION);
+    true
+}
+
+// Moves `circle` back to `position` if that moves it further away from `other`,
+// and returns the resulting squared distance between the two
+fn undo_move(circle: &mut Circle, position: Vector, other: Rectangle, diff: i32) -> i32 {
+    let (x, y) = position.to_pixels();
+    let mut rect = circle.rect;
+    rect.dx = x;
+    rect.dy = y;
+
+    let diff2 = circle_distance(rect, other);
+    if diff2 > diff {
+        // The circle bumps into the other one and stops along that axis
+        if position.x != circle.position.x {
+            circle.velocity.x = 0;
+        }
+        if position.y != circle.position.y {
+            circle.velocity.y = 0;
+        }
+        circle.set_position(position);
+        diff2
+    } else {
+        diff
+    }
 }
 
 fn detect_collission(env: &GameEnv, rect: Rectangle) -> bool {
+    env.obstacles.iter().any(|obs| hits_obstacle(obs, rect))
+}
+
+fn hits_obstacle(obs: &Obstacle, rect: Rectangle) -> bool {
     let dx: i32 = rect.dx as i32;
     let dy: i32 = rect.dy as i32;
 
-    let obs = &env.obstacle;
+    let line = obs.pos;
 
-    if dy <= env.frame && dy + 50 >= env.frame { // y is right for collission
+    if dy <= line && dy + 50 >= line { // y is right for collission
         if dx + 25 > obs.gap1.0 && dx + 25 < obs.gap1.1 {
-            let diffy: i32 = (dy + 25 - env.frame) * (dy + 25 - env.frame);
+            let diffy: i32 = (dy + 25 - line) * (dy + 25 - line);
             let mut diff: i32 = (dx + 25 - obs.gap1.0) * (dx + 25 - obs.gap1.0) + diffy;
 
             if diff < 625 {
@@ -308,7 +700,7 @@ fn detect_collission(env: &GameEnv, rect: Rectangle) -> bool {
         } else {
             match obs.gap2 {
                 Some((start, end)) if dx + 25 > start && dx + 25 < end => {
-                    let diffy: i32 = (dy as i32 + 25 - env.frame) * (dy as i32 + 25 - env.frame);
+                    let diffy: i32 = (dy as i32 + 25 - line) * (dy as i32 + 25 - line);
                     let mut diff: i32 = (dx + 25 - start) * (dx + 25 - start) + diffy;
                     if diff < 625 {
                         return true;
@@ -328,7 +720,7 @@ fn detect_collission(env: &GameEnv, rect: Rectangle) -> bool {
     false
 }
 
-fn generate_obstacle<R: Rng>(rng: &mut R) -> Obstacle {
+fn generate_obstacle<R: Rng>(rng: &mut R, level: &Level) -> Obstacle {
     let mut obstacle = Obstacle {
         rect: Rectangle {
             dx: 0,
@@ -338,14 +730,17 @@ fn generate_obstacle<R: Rng>(rng: &mut R) -> Obstacle {
         },
         obstacle: [true; display::WIDTH],
         pos: 0,
+        y: 0,
+        drawn: None,
         gap1: (0, 0),
         gap2: None,
     };
 
 
-    let generate_gap2 = rng.gen::<bool>();
-    let gap_size = if generate_gap2 { 70 } else { 90 };
-    let gap_area = if generate_gap2 { 90 } else { 230 };
+    let generate_gap2 = rng.gen_range(0, 256) < level.split_chance;
+    let gap_size = if generate_gap2 { level.split_gap } else { level.gap };
+    // With two gaps, each of them is placed within its own half of the screen
+    let gap_area = if generate_gap2 { 160 - gap_size } else { 320 - gap_size };
 
     obstacle.gap1.0 = rng.gen_range(0, gap_area);
     obstacle.gap1.1 = obstacle.gap1.0 + gap_size + 1;
@@ -372,38 +767,52 @@ fn generate_obstacle<R: Rng>(rng: &mut R) -> Obstacle {
     obstacle
 }
 
-fn update_obstacle<R: Rng>(env: &mut GameEnv, rng: &mut R) {
-    env.obstacle.rect.dy = env.frame - 2;
-    if env.obstacle.rect.dy < 0 {
-        env.obstacle.rect.dy = 0;
+fn update_obstacles<F: FrameBuffer, R: Rng>(fb: &mut F, env: &mut GameEnv, rng: &mut R) {
+    let level = difficulty::level(env.score);
+
+    for obstacle in env.obstacles.iter_mut() {
+        obstacle.rect.dy = cmp::max(obstacle.pos - 2, 0);
+
+        obstacle.y += level.speed;
+        obstacle.pos = obstacle.y >> 8;
     }
 
-    env.frame += 1;
-    env.obstacle.pos += display::V_WIDTH as usize;
-    if env.frame == 240 {
+    // Score the obstacles that have left the screen
+    while !env.obstacles.is_empty() && env.obstacles[0].pos >= display::HEIGHT as i32 {
+        let obstacle = env.obstacles.remove(0);
+        display::clear_obstacle(fb, &obstacle, env.theme, &env.background, &mut env.dirty);
+
         env.score += 1;
         if env.score > env.max_score {
             env.max_score = env.score;
         }
+        env.particles.sparkle(env.score_field.rect(), env.theme.score);
+    }
 
-        env.frame = 1;
-        env.obstacle = generate_obstacle(rng);
+    // Start a new obstacle once the last one is far enough down
+    let spawn = match env.obstacles.last() {
+        Some(obstacle) => obstacle.y >> 8 >= level.spacing,
+        None => true,
+    };
+    if spawn {
+        env.obstacles.push(generate_obstacle(rng, &difficulty::level(env.score)));
     }
 
-    display::draw_obstacle(&env.obstacle);
+    display::draw_obstacles(fb, &env.obstacles, env.theme, &env.background, &mut env.dirty);
+    for obstacle in env.obstacles.iter_mut() {
+        obstacle.drawn = Some(obstacle.pos);
+    }
 }
 
+#[cfg(not(feature = "sim"))]
 #[no_mangle]
 pub extern fn on_systick(ms_ticks: u32) {
 
-    if BENCHMARK_MODE {
+    if ms_ticks % 1000 == 0 {
 
-        if ms_ticks % 1000 == 0 {
-
-            unsafe {
-                LAST_FRAME_COUNT = FRAME_COUNT;
-                FRAME_COUNT = 0
-            };
-        }
+        unsafe {
+            LAST_FRAME_COUNT = FRAME_COUNT;
+            FRAME_COUNT = 0
+        };
     }
 }
@@ -0,0 +1,187 @@
+// Particles, the small blocks that burst out of a circle when it hits an
+// obstacle, sparkle above the score and trail behind the circles.
+//
+// Every particle moves on its own, fixed-point like the circles, and fades
+// from its color to that of the background until its lifetime is over. There
+// is room for `MAX_PARTICLES` of them at once, and new ones are left out while
+// it is full, so nothing is allocated. Trails only get a share of that room,
+// as the circles leave them behind every few frames, and would otherwise take
+// up the room a burst needs.
+//
+// Particles are drawn in a pass of their own: `erase` draws the background
+// over where they were and marks each of them as dirty, so whatever was
+// beneath them is drawn again, and `draw` puts them back on top once they have
+// moved on with `advance`.
+
+use core::prelude::*;
+
+use Rectangle;
+use background::Background;
+use color::Rgb565;
+use display;
+use display::{Dirty, CIRCLE_POINTS, CIRCLE_SAMPLES};
+use draw;
+use framebuffer::FrameBuffer;
+use physics::Vector;
+use theme::Theme;
+
+// Most particles on screen at once
+pub const MAX_PARTICLES: usize = 64;
+
+// Width and height of a particle, in pixels
+const SIZE: i32 = 2;
+
+// Number of particles in a burst, which fly out from points of the outline
+const BURST: usize = 17;
+const BURST_FRAMES: u16 = 30;
+// Pull towards the bottom of the screen every frame, in 1/256 pixels
+const BURST_GRAVITY: i32 = 6;
+
+const SPARKLE: usize = 6;
+const SPARKLE_FRAMES: u16 = 20;
+
+const TRAIL_FRAMES: u16 = 12;
+// A circle leaves a particle behind every this many frames
+const TRAIL_INTERVAL: u32 = 3;
+// The first slots are kept for trails, which are enough for four circles
+const TRAIL_SLOTS: usize = 16;
+
+#[derive(Copy, Clone)]
+struct Particle {
+    // Top left corner and movement per frame, in fixed-point
+    position: Vector,
+    velocity: Vector,
+    gravity: i32,
+    color: Rgb565,
+    age: u16,
+    lifetime: u16,
+    // Top left corner where the particle was last drawn
+    drawn: Option<(i32, i32)>,
+}
+
+impl Particle {
+    fn new(position: Vector, velocity: Vector, gravity: i32, color: Rgb565, lifetime: u16) -> Particle {
+        Particle {
+            position: position,
+            velocity: velocity,
+            gravity: gravity,
+            color: color,
+            age: 0,
+            lifetime: lifetime,
+            drawn: None,
+        }
+    }
+
+    // The color faded towards the background for the age of the particle
+    fn color(&self, theme: &Theme) -> Rgb565 {
+        let amount = self.age as u32 * 255 / self.lifetime as u32;
+        self.color.blend(theme.background, amount as u8)
+    }
+}
+
+pub struct Particles {
+    particles: [Option<Particle>; MAX_PARTICLES],
+    // Number of times the particles moved on, to space out the trails
+    frame: u32,
+}
+
+impl Particles {
+    pub fn new() -> Particles {
+        Particles {
+            particles: [None; MAX_PARTICLES],
+            frame: 0,
+        }
+    }
+
+    /// Bursts out of a circle around `center`, in the colors of its outline.
+    pub fn burst<C: Fn(usize) -> Rgb565>(&mut self, center: (i32, i32), colors: C) {
+        let (cx, cy) = center;
+        for i in 0 .. BURST {
+            let point = i * CIRCLE_SAMPLES / BURST;
+            let (x, y) = CIRCLE_POINTS[point];
+
+            // In half pixels per frame, faster for every other particle so
+            // they don't fly out as a ring
+            let speed = if i % 2 == 0 { 2 } else { 3 };
+            let velocity = Vector::new(x * speed * 128 / 24, y * speed * 128 / 24);
+            let position = Vector::from_pixels(cx + x, cy + y);
+            self.spawn(Particle::new(position, velocity, BURST_GRAVITY, colors(point), BURST_FRAMES),
+                       TRAIL_SLOTS, MAX_PARTICLES);
+        }
+    }
+
+    /// Sparkles rising from the top of a rectangle, e.g. the score.
+    pub fn sparkle(&mut self, rect: Rectangle, color: Rgb565) {
+        for i in 0 .. SPARKLE as i32 {
+            let x = rect.dx + i * (rect.width - SIZE) / (SPARKLE as i32 - 1);
+            let velocity = Vector::new((i % 3 - 1) * 32, -(96 + (i * 5 % 3) * 32));
+            let position = Vector::from_pixels(x, rect.dy - SIZE);
+            self.spawn(Particle::new(position, velocity, 0, color, SPARKLE_FRAMES), TRAIL_SLOTS, MAX_PARTICLES);
+        }
+    }
+
+    /// Leaves a particle standing where a circle was, centered on `center`,
+    /// once every few frames.
+    pub fn trail(&mut self, center: (i32, i32), color: Rgb565) {
+        if self.frame % TRAIL_INTERVAL != 0 {
+            return;
+        }
+
+        let (x, y) = center;
+        let position = Vector::from_pixels(x - SIZE / 2, y - SIZE / 2);
+        self.spawn(Particle::new(position, Vector::new(0, 0), 0, color, TRAIL_FRAMES), 0, TRAIL_SLOTS);
+    }
+
+    /// Draws the background over every particle on screen, and marks each of
+    /// them as dirty.
+    pub fn erase<F: FrameBuffer>(&mut self, fb: &mut F, theme: &Theme, background: &Background,
+                                 dirty: &mut Dirty) {
+        let clip = display::clip_rect(fb);
+
+        for particle in self.particles.iter_mut().filter_map(|particle| particle.as_mut()) {
+            if let Some((x, y)) = particle.drawn.take() {
+                for py in y .. y + SIZE {
+                    for px in x .. x + SIZE {
+                        if clip.contains_point(px, py) {
+                            let color = background.color(theme, px as usize, py as usize);
+                            fb.set_pixel(px as usize, py as usize, color);
+                        }
+                    }
+                }
+                dirty.mark(Rectangle::new(x, y, SIZE, SIZE));
+            }
+        }
+    }
+
+    /// Moves every particle on to the next frame, and removes the ones whose
+    /// lifetime is over. Particles have to be erased before they move.
+    pub fn advance(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+        for slot in self.particles.iter_mut() {
+            if let Some(mut particle) = *slot {
+                particle.age += 1;
+                particle.velocity.y += particle.gravity;
+                particle.position = Vector::new(particle.position.x + particle.velocity.x,
+                                                particle.position.y + particle.velocity.y);
+                *slot = if particle.age < particle.lifetime { Some(particle) } else { None };
+            }
+        }
+    }
+
+    /// Draws every particle over whatever is beneath it.
+    pub fn draw<F: FrameBuffer>(&mut self, fb: &mut F, theme: &Theme) {
+        for particle in self.particles.iter_mut().filter_map(|particle| particle.as_mut()) {
+            let (x, y) = particle.position.to_pixels();
+            draw::fill_rect(fb, &Rectangle::new(x, y, SIZE, SIZE), particle.color(theme).0);
+            particle.drawn = Some((x, y));
+        }
+    }
+
+    // Adds a particle in one of the slots from `from` up to `to`, unless there
+    // is no room left in them
+    fn spawn(&mut self, particle: Particle, from: usize, to: usize) {
+        if let Some(slot) = self.particles[from .. to].iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(particle);
+        }
+    }
+}
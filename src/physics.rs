@@ -0,0 +1,64 @@
+// Movement of the circles.
+//
+// Positions and velocities are fixed-point numbers with `FRACTION_BITS`
+// fractional bits, so a velocity of 256 moves a circle one pixel per frame.
+// Every frame a pressed direction accelerates the circle along its axis, up to
+// the maximum speed, and friction slows it down along the axes that have
+// nothing pressed.
+
+use core::prelude::*;
+use core::cmp;
+
+pub const FRACTION_BITS: u32 = 8;
+
+#[derive(Copy, Clone)]
+pub struct Physics {
+    // Change of velocity per frame while a direction is pressed
+    pub acceleration: i32,
+    // Highest speed along each axis
+    pub max_speed: i32,
+    // Change of velocity per frame towards standing still
+    pub friction: i32,
+}
+
+pub const DEFAULT: Physics = Physics {
+    acceleration: 64,
+    max_speed: 320,
+    friction: 48,
+};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Vector {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Vector {
+    pub fn new(x: i32, y: i32) -> Vector {
+        Vector { x: x, y: y }
+    }
+
+    /// The vector with both components given in whole pixels.
+    pub fn from_pixels(x: i32, y: i32) -> Vector {
+        Vector::new(x << FRACTION_BITS, y << FRACTION_BITS)
+    }
+
+    /// The whole pixels of both components, rounded down.
+    pub fn to_pixels(&self) -> (i32, i32) {
+        (self.x >> FRACTION_BITS, self.y >> FRACTION_BITS)
+    }
+}
+
+/// The velocity after one frame of pressing `direction` along an axis, which is
+/// -1, 0 or 1.
+pub fn accelerate(physics: &Physics, velocity: i32, direction: i32) -> i32 {
+    let velocity = if direction != 0 {
+        velocity + direction * physics.acceleration
+    } else if velocity > 0 {
+        cmp::max(velocity - physics.friction, 0)
+    } else {
+        cmp::min(velocity + physics.friction, 0)
+    };
+
+    cmp::max(cmp::min(velocity, physics.max_speed), -physics.max_speed)
+}
@@ -32,6 +32,16 @@ impl PRandom {
         PRandom { prndindex: 0 }
     }
 
+    /// Creates a generator that continues from a previously captured state.
+    pub fn with_state(state: u8) -> PRandom {
+        PRandom { prndindex: state as usize }
+    }
+
+    /// The current position in the random table, enough to reproduce the sequence.
+    pub fn state(&self) -> u8 {
+        self.prndindex as u8
+    }
+
 }
 
 impl Rng for PRandom {
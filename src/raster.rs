@@ -0,0 +1,207 @@
+// Raster effects, which move lines of the screen sideways while it is shown.
+//
+// The panel reads every line from an address the EBI interrupt sets at the
+// start of the line, so moving that address by a few pixels moves the line
+// without drawing anything. Effects are started from the game for a number of
+// frames on a band of lines, and `Effects::offset` adds them up for every line
+// of the next frame, which `display::set_line_offsets` hands to the interrupt
+// along with the page it belongs to.
+//
+// The columns moved in from beyond the edge of a page show the spare columns
+// of the virtual display or the edge of the other page. A line moved by more
+// than the spare columns would show part of the next line, so the offsets are
+// kept within `MAX_OFFSET`.
+
+use core::prelude::*;
+use core::cmp;
+use core::u32;
+
+use display::{WIDTH, HEIGHT, V_WIDTH};
+
+/// Furthest a line is moved either way, in pixels.
+pub const MAX_OFFSET: i32 = (V_WIDTH - 2 * WIDTH) as i32;
+
+/// Number of frames of an effect that runs until it is stopped.
+pub const FOREVER: u32 = u32::MAX;
+
+// Most effects running at once
+const MAX_EFFECTS: usize = 4;
+
+// The steps of a shake, in eighths of its intensity
+const SHAKE: [i32; 8] = [8, -6, 7, -8, 3, -5, 6, -2];
+
+// A quarter of a sine wave in 16ths of a half turn, in 64ths
+const QUARTER_SINE: [i32; 9] = [0, 12, 25, 36, 45, 53, 59, 63, 64];
+
+// Number of lines in a wave, which moves down by a line every frame
+const WAVE_LINES: u32 = 32;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    /// The band jumps from side to side, calming down towards the end.
+    Shake,
+    /// The lines of the band sway in a wave running down the screen.
+    Wave,
+    /// The band starts moved aside and slides back into place, while the
+    /// lines outside of it stay where they are.
+    Slide,
+}
+
+/// The lines from `top` up to `bottom`.
+#[derive(Copy, Clone)]
+pub struct Band {
+    pub top: usize,
+    pub bottom: usize,
+}
+
+pub const SCREEN: Band = Band { top: 0, bottom: HEIGHT };
+
+/// Everything below the score.
+pub const PLAYFIELD: Band = Band { top: 24, bottom: HEIGHT };
+
+#[derive(Copy, Clone)]
+pub struct Effect {
+    pub kind: Kind,
+    pub band: Band,
+    /// How long the effect runs, or `FOREVER`.
+    pub frames: u32,
+    /// How far the lines move at most, in pixels. Positive values move them
+    /// to the left.
+    pub intensity: i32,
+}
+
+#[derive(Copy, Clone)]
+struct Running {
+    effect: Effect,
+    // Frames since the effect started
+    frame: u32,
+}
+
+impl Running {
+    // The intensity in the current frame, fading out towards the end for the
+    // effects that do
+    fn intensity(&self) -> i32 {
+        let effect = self.effect;
+        if effect.frames == FOREVER {
+            effect.intensity
+        } else {
+            effect.intensity * (effect.frames - self.frame) as i32 / effect.frames as i32
+        }
+    }
+
+    fn offset(&self, line: usize) -> i32 {
+        match self.effect.kind {
+            Kind::Shake => self.intensity() * SHAKE[self.frame as usize % SHAKE.len()] / 8,
+            Kind::Wave => {
+                let step = (line as u32).wrapping_sub(self.frame) % WAVE_LINES;
+                self.effect.intensity * sine(step) / 64
+            },
+            Kind::Slide => self.intensity(),
+        }
+    }
+}
+
+/// The raster effects running on the screen.
+pub struct Effects {
+    running: [Option<Running>; MAX_EFFECTS],
+}
+
+impl Effects {
+    pub fn new() -> Effects {
+        Effects {
+            running: [None; MAX_EFFECTS],
+        }
+    }
+
+    /// Starts an effect from the current frame on, in place of the one that
+    /// has been running the longest if there are too many already. An effect
+    /// of no frames is over before it starts, and never runs.
+    pub fn start(&mut self, effect: Effect) {
+        if effect.frames == 0 {
+            return;
+        }
+
+        let mut slot = 0;
+        for i in 0 .. MAX_EFFECTS {
+            match (self.running[i], self.running[slot]) {
+                (None, _) => {
+                    slot = i;
+                    break;
+                },
+                (Some(running), Some(oldest)) if running.frame > oldest.frame => slot = i,
+                _ => {},
+            }
+        }
+
+        self.running[slot] = Some(Running { effect: effect, frame: 0 });
+    }
+
+    pub fn stop_all(&mut self) {
+        for running in self.running.iter_mut() {
+            *running = None;
+        }
+    }
+
+    /// Moves every effect on to the next frame, and stops the ones that are over.
+    pub fn advance(&mut self) {
+        for slot in self.running.iter_mut() {
+            if let Some(mut running) = *slot {
+                running.frame += 1;
+                *slot = if running.frame < running.effect.frames { Some(running) } else { None };
+            }
+        }
+    }
+
+    /// How far a line is moved in the current frame, in pixels to the left.
+    pub fn offset(&self, line: usize) -> i32 {
+        let mut offset = 0;
+        for running in self.running.iter().filter_map(|running| running.as_ref()) {
+            let band = running.effect.band;
+            if line >= band.top && line < band.bottom {
+                offset += running.offset(line);
+            }
+        }
+        cmp::max(cmp::min(offset, MAX_OFFSET), -MAX_OFFSET)
+    }
+}
+
+// The sine of `step` 32nds of a turn, in 64ths
+fn sine(step: u32) -> i32 {
+    let half = (step % 32) / 16;
+    let step = (step % 16) as usize;
+    let value = if step <= 8 { QUARTER_SINE[step] } else { QUARTER_SINE[16 - step] };
+    if half == 0 { value } else { -value }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{Effect, Effects, Kind, PLAYFIELD};
+    use display::HEIGHT;
+
+    fn slide(frames: u32) -> Effect {
+        Effect { kind: Kind::Slide, band: PLAYFIELD, frames: frames, intensity: 8 }
+    }
+
+    #[test]
+    fn effect_of_no_frames_never_runs() {
+        let mut effects = Effects::new();
+        effects.start(slide(0));
+        for line in 0 .. HEIGHT {
+            assert_eq!(effects.offset(line), 0);
+        }
+        effects.advance();
+        assert_eq!(effects.offset(PLAYFIELD.top), 0);
+    }
+
+    #[test]
+    fn slide_moves_its_band_back_into_place() {
+        let mut effects = Effects::new();
+        effects.start(slide(2));
+        assert_eq!(effects.offset(PLAYFIELD.top - 1), 0);
+        assert_eq!(effects.offset(PLAYFIELD.top), 8);
+        effects.advance();
+        assert_eq!(effects.offset(PLAYFIELD.top), 4);
+        effects.advance();
+        assert_eq!(effects.offset(PLAYFIELD.top), 0);
+    }
+}
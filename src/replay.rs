@@ -0,0 +1,320 @@
+// Recording and replay of game sessions.
+//
+// A recording starts with a header followed by one record per frame, and an
+// extra record after every frame that ended in a restart:
+//
+//   header:  "CGRP" | version: u8 | rng state: u8 | frame count: u32 (LE) | players: u8
+//            | max score: u16 (LE)
+//   frame:   0x01 | buttons: u16 (LE) | checksum: u16 (LE)
+//   restart: 0x02 | rng state: u8
+//
+// The rng state in the header is the state of `PRandom` before the first call
+// to `restart`, and the max score is the high score the game started with. The
+// checksum covers the game state after the frame has been played, which lets a
+// replay report the first frame where it diverges.
+
+use core::prelude::*;
+use collections::vec::Vec;
+
+use {GameEnv, MAX_CIRCLES};
+use controller::{Controller, BUTTONS, RELEASED};
+use state::Game;
+
+const MAGIC: [u8; 4] = [b'C', b'G', b'R', b'P'];
+pub const VERSION: u8 = 3;
+
+const HEADER_LEN: usize = 13;
+const FRAME_LEN: usize = 5;
+const RESTART_LEN: usize = 2;
+
+const TAG_FRAME: u8 = 0x01;
+const TAG_RESTART: u8 = 0x02;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadPlayers(u8),
+    BadRecord(usize),
+    Truncated,
+}
+
+#[derive(Copy, Clone)]
+pub struct ReplayFrame {
+    pub buttons: u32,
+    pub checksum: u16,
+    // Random state at the restart this frame ended in, if any
+    pub restart: Option<u8>,
+}
+
+pub struct Recorder {
+    data: Vec<u8>,
+    frames: u32,
+    max_len: usize,
+}
+
+impl Recorder {
+    /// Starts a new recording of a game with `players` circles and the given
+    /// high score that will never grow beyond `max_len` bytes.
+    pub fn new(rng_state: u8, players: usize, max_score: u32, max_len: usize) -> Recorder {
+        let mut data = Vec::with_capacity(if max_len < 1024 { max_len } else { 1024 });
+        for &b in MAGIC.iter() {
+            data.push(b);
+        }
+        data.push(VERSION);
+        data.push(rng_state);
+        push_u32(&mut data, 0);
+        data.push(players as u8);
+        data.push(max_score as u8);
+        data.push((max_score >> 8) as u8);
+
+        Recorder {
+            data: data,
+            frames: 0,
+            max_len: max_len,
+        }
+    }
+
+    /// Records the buttons used for a frame and the state it resulted in.
+    ///
+    /// Returns `false` once the recording is full.
+    pub fn record_frame(&mut self, buttons: u32, env: &GameEnv) -> bool {
+        // Always leave room for the restart that may follow the frame
+        if self.data.len() + FRAME_LEN + RESTART_LEN > self.max_len {
+            return false;
+        }
+
+        let sum = checksum(env);
+        self.data.push(TAG_FRAME);
+        self.data.push(buttons as u8);
+        self.data.push((buttons >> 8) as u8);
+        self.data.push(sum as u8);
+        self.data.push((sum >> 8) as u8);
+
+        // Keep the header up to date so the buffer is a valid recording at all times
+        self.frames += 1;
+        let frames = self.frames;
+        write_u32(&mut self.data[6 .. 10], frames);
+
+        true
+    }
+
+    /// Marks the last recorded frame as ending in a restart with the given random state.
+    pub fn record_restart(&mut self, rng_state: u8) {
+        if self.frames > 0 && self.data.len() + RESTART_LEN <= self.max_len {
+            self.data.push(TAG_RESTART);
+            self.data.push(rng_state);
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+pub struct Replay<'a> {
+    data: &'a [u8],
+    pos: usize,
+    frame: u32,
+    // The frame handed out last, to be checked once it has been played
+    current: Option<ReplayFrame>,
+    divergence: Option<u32>,
+}
+
+impl<'a> Replay<'a> {
+    /// Validates a recording and prepares it for replay.
+    pub fn new(data: &'a [u8]) -> Result<Replay<'a>, ReplayError> {
+        if data.len() < HEADER_LEN {
+            return Err(ReplayError::Truncated);
+        }
+        if &data[0 .. 4] != &MAGIC[..] {
+            return Err(ReplayError::BadMagic);
+        }
+        if data[4] != VERSION {
+            return Err(ReplayError::UnsupportedVersion(data[4]));
+        }
+        if data[10] < 1 || data[10] as usize > MAX_CIRCLES {
+            return Err(ReplayError::BadPlayers(data[10]));
+        }
+
+        // Walk all records up front so replaying can never fail half way through
+        let mut pos = HEADER_LEN;
+        let mut frames = 0;
+        let mut after_frame = false;
+        while pos < data.len() {
+            let len = match data[pos] {
+                TAG_FRAME => FRAME_LEN,
+                // A restart always belongs to the frame before it
+                TAG_RESTART if after_frame => RESTART_LEN,
+                _ => return Err(ReplayError::BadRecord(pos)),
+            };
+            if pos + len > data.len() {
+                return Err(ReplayError::Truncated);
+            }
+            after_frame = data[pos] == TAG_FRAME;
+            if after_frame {
+                frames += 1;
+            }
+            pos += len;
+        }
+        if frames != read_u32(&data[6 .. 10]) {
+            return Err(ReplayError::Truncated);
+        }
+
+        Ok(Replay {
+            data: data,
+            pos: HEADER_LEN,
+            frame: 0,
+            current: None,
+            divergence: None,
+        })
+    }
+
+    /// The random state to seed `PRandom` with before the first restart.
+    pub fn rng_state(&self) -> u8 {
+        self.data[5]
+    }
+
+    pub fn frames(&self) -> u32 {
+        read_u32(&self.data[6 .. 10])
+    }
+
+    /// The number of circles in the recorded game.
+    pub fn players(&self) -> usize {
+        self.data[10] as usize
+    }
+
+    /// The high score the recorded game started with.
+    pub fn max_score(&self) -> u32 {
+        self.data[11] as u32 | (self.data[12] as u32) << 8
+    }
+
+    pub fn next_frame(&mut self) -> Option<ReplayFrame> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let record = &self.data[self.pos .. self.pos + FRAME_LEN];
+        let mut frame = ReplayFrame {
+            // The buttons that were not recorded are released
+            buttons: record[1] as u32 | (record[2] as u32) << 8 | !BUTTONS,
+            checksum: record[3] as u16 | (record[4] as u16) << 8,
+            restart: None,
+        };
+        self.pos += FRAME_LEN;
+        self.frame += 1;
+
+        if self.pos < self.data.len() && self.data[self.pos] == TAG_RESTART {
+            frame.restart = Some(self.data[self.pos + 1]);
+            self.pos += RESTART_LEN;
+        }
+
+        Some(frame)
+    }
+
+    /// Compares the outcome of replaying `frame` with the recording.
+    ///
+    /// `rng_state` is the random state before the frame was played. The first
+    /// frame that does not match is remembered and available from `divergence`.
+    pub fn check(&mut self, frame: &ReplayFrame, env: &GameEnv, rng_state: u8, restarted: bool) -> bool {
+        let matches = checksum(env) == frame.checksum && match frame.restart {
+            Some(state) => restarted && state == rng_state,
+            None => !restarted,
+        };
+
+        if !matches && self.divergence.is_none() {
+            self.divergence = Some(self.frame - 1);
+        }
+
+        matches
+    }
+
+    /// The index of the first frame that did not match the recording.
+    pub fn divergence(&self) -> Option<u32> {
+        self.divergence
+    }
+}
+
+impl<'a> Controller for Replay<'a> {
+    fn buttons(&mut self, _game: &Game) -> u32 {
+        self.current = self.next_frame();
+        match self.current {
+            Some(frame) => frame.buttons,
+            None => RELEASED,
+        }
+    }
+
+    fn frame_done(&mut self, env: &GameEnv, rng_state: u8, restarted: bool) {
+        if let Some(frame) = self.current.take() {
+            self.check(&frame, env, rng_state, restarted);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// Fletcher-16 checksum of everything that decides the outcome of a game.
+pub fn checksum(env: &GameEnv) -> u16 {
+    let mut sum = Fletcher16 { a: 0, b: 0 };
+
+    sum.add(env.score as i32);
+    sum.add(env.max_score as i32);
+
+    for circle in env.circles.iter() {
+        for &value in [circle.position.x, circle.position.y, circle.velocity.x, circle.velocity.y].iter() {
+            sum.add(value);
+        }
+    }
+
+    for obstacle in env.obstacles.iter() {
+        let gap2 = match obstacle.gap2 {
+            Some(gap) => gap,
+            None => (-1, -1),
+        };
+        for &value in [obstacle.y, obstacle.gap1.0, obstacle.gap1.1, gap2.0, gap2.1].iter() {
+            sum.add(value);
+        }
+    }
+
+    (sum.b << 8) | sum.a
+}
+
+struct Fletcher16 {
+    a: u16,
+    b: u16,
+}
+
+impl Fletcher16 {
+    fn add(&mut self, value: i32) {
+        for i in 0 .. 4 {
+            self.a = (self.a + ((value >> (8 * i)) & 0xff) as u16) % 255;
+            self.b = (self.b + self.a) % 255;
+        }
+    }
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    for i in 0 .. 4 {
+        data.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn write_u32(data: &mut [u8], value: u32) {
+    for i in 0 .. 4 {
+        data[i] = (value >> (8 * i)) as u8;
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    let mut value = 0;
+    for i in 0 .. 4 {
+        value |= (data[i] as u32) << (8 * i);
+    }
+    value
+}
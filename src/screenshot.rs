@@ -0,0 +1,148 @@
+// Screenshots of the frame buffer as BMP files.
+//
+// A screenshot is the visible part of the page being drawn, written out as an
+// uncompressed 16 bit BMP with bit masks for RGB565, so the pixels go out just
+// as they are in the frame buffer:
+//
+//   file header:  "BM" | file size: u32 | reserved: u32 | offset of the pixels: u32
+//   info header:  40: u32 | width: i32 | height: i32 | planes: u16 (1) | bits: u16 (16)
+//                 | compression: u32 (3, bit masks) | size of the pixels: u32
+//                 | resolution: i32, i32 | colors: u32, u32
+//   masks:        red: u32 | green: u32 | blue: u32
+//   pixels:       the lines from the bottom up, padded to a multiple of 4 bytes
+//
+// All numbers are little endian. On the board, holding left and right of the
+// second circle sends a screenshot over the RS-232 port of the DK3750 at
+// 115200 baud, 8N1, which takes about 13 seconds during which the game stands
+// still. `tools/screenshot` receives it on the host.
+
+#[cfg(not(feature = "sim"))]
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+#[cfg(feature = "sim")]
+use collections::vec::Vec;
+
+#[cfg(not(feature = "sim"))]
+use kits::dk::bsp;
+
+use framebuffer::FrameBuffer;
+
+// Length of the headers and masks before the pixels
+const HEADER_LEN: usize = 66;
+const INFO_LEN: u32 = 40;
+const BI_BITFIELDS: u32 = 3;
+
+/// Somewhere the bytes of a screenshot are sent.
+pub trait Serial {
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// Sends the page being drawn as a BMP file.
+pub fn write_bmp<F: FrameBuffer, S: Serial>(fb: &F, out: &mut S) {
+    let (width, height) = (fb.width(), fb.height());
+    let line_len = (2 * width + 3) / 4 * 4;
+    let pixels_len = line_len * height;
+
+    out.write_byte(b'B');
+    out.write_byte(b'M');
+    write_u32(out, (HEADER_LEN + pixels_len) as u32);
+    write_u32(out, 0);
+    write_u32(out, HEADER_LEN as u32);
+
+    write_u32(out, INFO_LEN);
+    write_u32(out, width as u32);
+    write_u32(out, height as u32);
+    write_u16(out, 1);
+    write_u16(out, 16);
+    write_u32(out, BI_BITFIELDS);
+    write_u32(out, pixels_len as u32);
+    // 72 DPI
+    write_u32(out, 2835);
+    write_u32(out, 2835);
+    write_u32(out, 0);
+    write_u32(out, 0);
+
+    write_u32(out, 0xf800);
+    write_u32(out, 0x07e0);
+    write_u32(out, 0x001f);
+
+    for y in (0 .. height).rev() {
+        for x in 0 .. width {
+            write_u16(out, fb.read(y * fb.stride() + x));
+        }
+        for _ in 2 * width .. line_len {
+            out.write_byte(0);
+        }
+    }
+}
+
+fn write_u16<S: Serial>(out: &mut S, value: u16) {
+    out.write_byte(value as u8);
+    out.write_byte((value >> 8) as u8);
+}
+
+fn write_u32<S: Serial>(out: &mut S, value: u32) {
+    write_u16(out, value as u16);
+    write_u16(out, (value >> 16) as u16);
+}
+
+#[cfg(feature = "sim")]
+impl Serial for Vec<u8> {
+    fn write_byte(&mut self, byte: u8) {
+        self.push(byte);
+    }
+}
+
+// UART1 at location 2, which the board controller connects to the RS-232 port
+#[cfg(not(feature = "sim"))]
+const UART1_BASE: u32 = 0x4000e400;
+#[cfg(not(feature = "sim"))]
+const UART_STATUS_TXBL: u32 = 1 << 6;
+
+/// Sets up the RS-232 port for sending screenshots, with the core clock at 48MHz.
+#[cfg(not(feature = "sim"))]
+pub fn init() {
+    let cmu_hfperclken0: *mut u32 = (0x400c8000 + 0x44) as *mut u32;
+    let gpio_pb_base: u32         = 0x40006024;
+    let gpio_pb_modeh: *mut u32   = (gpio_pb_base + 0x08) as *mut u32;
+    let gpio_pb_dout: *mut u32    = (gpio_pb_base + 0x0c) as *mut u32;
+    let uart_clkdiv: *mut u32     = (UART1_BASE + 0x14) as *mut u32;
+    let uart_cmd: *mut u32        = (UART1_BASE + 0x0c) as *mut u32;
+    let uart_route: *mut u32      = (UART1_BASE + 0x54) as *mut u32;
+
+    bsp::peripheral_access(bsp::RS232_UART, true);
+
+    unsafe {
+        // Enable the clock of UART1
+        volatile_store(cmu_hfperclken0, volatile_load(cmu_hfperclken0) | 1 << 4);
+
+        // Only sending is needed, on PB9, which is a push-pull output that idles high
+        let modeh = volatile_load(gpio_pb_modeh) & !0xf0;
+        volatile_store(gpio_pb_modeh, modeh | 0x40);
+        volatile_store(gpio_pb_dout, volatile_load(gpio_pb_dout) | 1 << 9);
+
+        // 48MHz / (16 * (1 + 6400 / 256)) is 115385 baud, the frame is 8N1 after reset
+        volatile_store(uart_clkdiv, 6400);
+        volatile_store(uart_route, 2 << 8 | 0x2);
+        // Enable the transmitter
+        volatile_store(uart_cmd, 1 << 2);
+    }
+}
+
+/// The RS-232 port of the DK3750, once set up with `init`.
+#[cfg(not(feature = "sim"))]
+pub struct Uart;
+
+#[cfg(not(feature = "sim"))]
+impl Serial for Uart {
+    fn write_byte(&mut self, byte: u8) {
+        let uart_status: *const u32 = (UART1_BASE + 0x10) as *const u32;
+        let uart_txdata: *mut u32 = (UART1_BASE + 0x34) as *mut u32;
+
+        unsafe {
+            // Wait for room in the transmit buffer
+            while volatile_load(uart_status) & UART_STATUS_TXBL == 0 {}
+            volatile_store(uart_txdata, byte as u32);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+// Headless simulator for running the game loop on the host.
+//
+// Build and run with `cargo run --features sim -- [frames] [options]`. The game
+// renders into a RAM frame buffer laid out like the EBI bank, so the gameplay
+// code can be exercised without a DK3750 at hand.
+//
+// Options:
+//   --players <n>      number of circles, from 1 to 4, defaults to 2
+//   --circle<i> <input>
+//                      controller of the i-th circle, defaults to `ai`
+//   --record <file>    write the session to a recording
+//   --replay <file>    replay a recording instead of using the controllers, and
+//                      report the first frame where the replay diverges from it
+//   --flash <file>     keep the high scores in an image of the flash pages, the
+//                      way the board keeps them in its on-chip flash
+//   --power-loss <n>   cut the power in the middle of the n-th flash operation,
+//                      which ends the session like a reset would
+//   --screenshot <file>
+//                      write the screen at the end of the session to a BMP file,
+//                      the way the board sends it over its RS-232 port
+//
+// An input is either `ai`, `idle`, a repeating script of hexadecimal button
+// states and the number of frames to hold them, e.g. `script:fb*20,fe*40`, or
+// the buttons of a recording, e.g. `replay:session.cgr`. Only the bits of its
+// own circle are taken from a script or recording, so the third circle would be
+// moved with e.g. `script:bff*20,eff*40`.
+
+use std::cmp;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::process;
+use std::usize;
+
+use prand::PRandom;
+use display::{WIDTH, HEIGHT, V_WIDTH, V_HEIGHT};
+use framebuffer::{DoubleBuffer, RamFrameBuffer};
+use replay::{Recorder, Replay};
+use state::{Game, State};
+use controller::{Controller, Input, Players, Script, RELEASED};
+use ai::Ai;
+use flash::MemoryFlash;
+use highscore::{HighScores, ScoreStore};
+use screenshot;
+use MAX_CIRCLES;
+
+const DEFAULT_FRAMES: u32 = 10000;
+const DEFAULT_PLAYERS: usize = 2;
+
+// Same layout as the flash pages used on the board
+const FLASH_PAGES: usize = 2;
+const FLASH_PAGE_SIZE: usize = 4096;
+
+pub fn main() {
+    let mut frames = DEFAULT_FRAMES;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut flash_path = None;
+    let mut power_loss = None;
+    let mut screenshot_path = None;
+    let mut players = DEFAULT_PLAYERS;
+    let mut choices = vec![Choice::Ai; MAX_CIRCLES];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--record" => record_path = args.next(),
+            "--replay" => replay_path = args.next(),
+            "--flash" => flash_path = args.next(),
+            "--screenshot" => screenshot_path = args.next(),
+            "--power-loss" => power_loss = match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => Some(n),
+                _ => usage(),
+            },
+            "--players" => players = match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n >= 1 && n <= MAX_CIRCLES => n,
+                _ => usage(),
+            },
+            _ if arg.starts_with("--circle") => match arg["--circle".len() ..].parse::<usize>() {
+                Ok(i) if i >= 1 && i <= MAX_CIRCLES => choices[i - 1] = parse_input(args.next()),
+                _ => usage(),
+            },
+            _ => match arg.parse::<u32>() {
+                Ok(n) => frames = n,
+                Err(_) => usage(),
+            },
+        }
+    }
+
+    let replay_data = replay_path.map(|path| read_file(&path));
+
+    let mut store = flash_path.as_ref().map(|path| {
+        let image = match File::open(path) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            _ => read_file(path),
+        };
+        ScoreStore::open(MemoryFlash::from_image(&image, FLASH_PAGES, FLASH_PAGE_SIZE))
+    });
+    if let (Some(store), Some(n)) = (store.as_mut(), power_loss) {
+        store.flash_mut().cut_power_after(n);
+    }
+
+    let mut buffer = vec![0u16; V_WIDTH * V_HEIGHT];
+    let mut fb = DoubleBuffer::new(RamFrameBuffer::new(&mut buffer, WIDTH, HEIGHT, V_WIDTH));
+
+    let recorder = match replay_data {
+        Some(ref data) => {
+            let mut replay = match Replay::new(data) {
+                Ok(replay) => replay,
+                Err(e) => fail(&format!("invalid recording: {:?}", e)),
+            };
+            let rng = PRandom::with_state(replay.rng_state());
+            let players = replay.players();
+
+            // Replays start from the recorded high score, and never save theirs
+            let mut scores = HighScores::new();
+            scores.insert(replay.max_score());
+            let recorder = play(&mut fb, &mut replay, rng, players, scores, None, ::std::u32::MAX);
+
+            match replay.divergence() {
+                Some(frame) => fail(&format!("replay diverged at frame {}", frame)),
+                None => println!("replay matches the recording"),
+            }
+            recorder
+        },
+        None => {
+            let mut controller = Players {
+                controllers: choices[.. players].iter().map(|choice| input(choice)).collect(),
+            };
+            let scores = store.as_ref().and_then(|store| store.scores()).unwrap_or(HighScores::new());
+            play(&mut fb, &mut controller, PRandom::new(), players, scores, store.as_mut(), frames)
+        },
+    };
+
+    if let Some(path) = record_path {
+        match File::create(&path).and_then(|mut file| file.write_all(recorder.data())) {
+            Ok(()) => println!("recorded {} frames to {}", recorder.frames(), path),
+            Err(e) => fail(&format!("{}: {}", path, e)),
+        }
+    }
+
+    if let Some(path) = screenshot_path {
+        let mut bmp = Vec::new();
+        screenshot::write_bmp(&fb, &mut bmp);
+        if let Err(e) = File::create(&path).and_then(|mut file| file.write_all(&bmp)) {
+            fail(&format!("{}: {}", path, e));
+        }
+    }
+
+    if let (Some(path), Some(store)) = (flash_path, store) {
+        if let Err(e) = File::create(&path).and_then(|mut file| file.write_all(&store.flash().image())) {
+            fail(&format!("{}: {}", path, e));
+        }
+    }
+}
+
+// Plays until the controller runs out of input or `frames` frames have been played
+fn play<C: Controller>(fb: &mut DoubleBuffer<RamFrameBuffer>, controller: &mut C, mut rng: PRandom,
+                       players: usize, scores: HighScores,
+                       mut store: Option<&mut ScoreStore<MemoryFlash>>, frames: u32) -> Recorder {
+    let mut recorder = Recorder::new(rng.state(), players, scores.best(), usize::MAX);
+    let mut game = Game::new(fb, &mut rng, players, scores);
+    let mut saved = scores;
+    let mut played = 0;
+    let mut deaths = 0;
+    let mut pixels = 0;
+
+    while played < frames {
+        let rng_state = rng.state();
+        let buttons = controller.buttons(&game);
+
+        let state = game.state();
+        // The panel is refreshed once every frame
+        game.scroll(played);
+        let restarted = game.update(fb, &mut rng, buttons);
+        fb.flip();
+        pixels += fb.pixels_written() as u64;
+        played += 1;
+
+        if state != State::Crashed && game.state() == State::Crashed {
+            deaths += 1;
+        }
+
+        recorder.record_frame(buttons, game.env());
+        if restarted {
+            recorder.record_restart(rng_state);
+        }
+        controller.frame_done(game.env(), rng_state, restarted);
+
+        if controller.is_finished() {
+            break;
+        }
+
+        if *game.scores() != saved {
+            saved = *game.scores();
+            if let Some(ref mut store) = store {
+                if let Err(e) = store.save(&saved) {
+                    let _ = writeln!(io::stderr(), "saving the high scores failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    println!("frames: {}, deaths: {}, score: {}, max score: {}",
+             played, deaths, game.env().score, game.env().max_score);
+    println!("high scores: {:?}", game.scores().scores());
+    println!("pixels written per frame: {}", pixels / cmp::max(played, 1) as u64);
+
+    recorder
+}
+
+// The input of a circle, before it is turned into a controller
+#[derive(Clone)]
+enum Choice {
+    Ai,
+    Script(Vec<(u32, u32)>),
+    Replay(Vec<u8>),
+}
+
+fn parse_input(arg: Option<String>) -> Choice {
+    let arg = match arg {
+        Some(arg) => arg,
+        None => usage(),
+    };
+
+    match &*arg {
+        "ai" => return Choice::Ai,
+        "idle" => return Choice::Script(vec![(RELEASED, 1)]),
+        _ => (),
+    }
+    if arg.starts_with("replay:") {
+        return Choice::Replay(read_file(&arg["replay:".len() ..]));
+    }
+    if !arg.starts_with("script:") {
+        usage();
+    }
+
+    Choice::Script(arg["script:".len() ..].split(',').map(|step| {
+        let mut parts = step.split('*');
+        let buttons = parts.next().and_then(|b| u32::from_str_radix(b, 16).ok());
+        let frames = parts.next().and_then(|f| f.parse::<u32>().ok());
+        // A step has to be held for at least a frame
+        match (buttons, frames) {
+            (Some(buttons), Some(frames)) if frames > 0 => (buttons, frames),
+            _ => usage(),
+        }
+    }).collect())
+}
+
+fn input(choice: &Choice) -> Input {
+    match *choice {
+        Choice::Ai => Input::Ai(Ai::new()),
+        Choice::Script(ref steps) => Input::Script(Script::new(steps, true)),
+        Choice::Replay(ref data) => match Replay::new(data) {
+            Ok(replay) => Input::Replay(replay),
+            Err(e) => fail(&format!("invalid recording: {:?}", e)),
+        },
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut data)) {
+        Ok(_) => data,
+        Err(e) => fail(&format!("{}: {}", path, e)),
+    }
+}
+
+fn usage() -> ! {
+    fail("usage: circle-game [frames] [--players <n>] [--circle<i> <input>]... \
+          [--record <file>] [--replay <file>] [--flash <file>] [--power-loss <n>] \
+          [--screenshot <file>]")
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    process::exit(1)
+}
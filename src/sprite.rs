@@ -0,0 +1,188 @@
+// Sprites, bitmaps drawn on top of what is already on screen.
+//
+// The pixels of a sprite are stored row by row as indices into a palette of up
+// to 256 colors, and run-length encoded as a sequence of `count, index` pairs,
+// where a run may continue on the next row.
+//
+// The sprites of the game are converted from the XPM images in
+// `assets/sprites` by the build script, which names each one after its file,
+// so `trophy.xpm` becomes `TROPHY`. They are `static`, so their data stays in
+// flash, and stored as run-length encoded palette indices.
+
+use core::prelude::*;
+use core::cmp;
+
+use framebuffer::FrameBuffer;
+
+#[derive(Copy, Clone)]
+pub enum Pixels<'a> {
+    /// Runs of palette indices, as pairs of the number of pixels and their index.
+    IndexedRle { palette: &'a [u16], runs: &'a [u8] },
+}
+
+#[derive(Copy, Clone)]
+pub struct Sprite<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Pixels<'a>,
+    /// Pixels of this color are left out, showing what is behind the sprite.
+    pub key: Option<u16>,
+}
+
+impl<'a> Sprite<'a> {
+    /// The pixels of the sprite as runs of the same color.
+    pub fn runs(&self) -> Runs<'a> {
+        Runs {
+            pixels: self.pixels,
+            pos: 0,
+        }
+    }
+}
+
+/// Iterates over the pixels of a sprite as `(count, color)` runs.
+pub struct Runs<'a> {
+    pixels: Pixels<'a>,
+    // Position in the data of the sprite
+    pos: usize,
+}
+
+impl<'a> Iterator for Runs<'a> {
+    type Item = (usize, u16);
+
+    fn next(&mut self) -> Option<(usize, u16)> {
+        let pos = self.pos;
+        let (run, len) = match self.pixels {
+            Pixels::IndexedRle { palette, runs } if pos + 1 < runs.len() => {
+                (Some((runs[pos] as usize, palette[runs[pos + 1] as usize])), 2)
+            },
+            _ => (None, 0),
+        };
+
+        self.pos += len;
+        run
+    }
+}
+
+/// Draws a sprite with its top left corner at `(x, y)`, clipped to the screen.
+pub fn blit<F: FrameBuffer>(fb: &mut F, sprite: &Sprite, x: i32, y: i32) {
+    let (width, height) = (sprite.width as i32, sprite.height as i32);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // The visible part of the sprite, in its own coordinates
+    let left = cmp::max(-x, 0);
+    let right = cmp::min(fb.width() as i32 - x, width);
+    let top = cmp::max(-y, 0);
+    let bottom = cmp::min(fb.height() as i32 - y, height);
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let stride = fb.stride();
+    let mut pos = 0;
+
+    for (count, color) in sprite.runs() {
+        let end = pos + count as i32;
+
+        // Split the run at the ends of the rows it covers
+        while pos < end {
+            let (row, col) = (pos / width, pos % width);
+            let len = cmp::min(end - pos, width - col);
+
+            if row >= bottom {
+                return;
+            }
+
+            let from = cmp::max(col, left);
+            let to = cmp::min(col + len, right);
+            if row >= top && from < to && Some(color) != sprite.key {
+                let idx = (y + row) as usize * stride + (x + from) as usize;
+                fb.fill(idx, (to - from) as usize, color);
+            }
+
+            pos += len;
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/sprites.rs"));
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{blit, Pixels, Sprite};
+    use framebuffer::RamFrameBuffer;
+
+    const BLANK: u16 = 0x1234;
+    const KEY: u16 = 0xf81f;
+
+    // A sprite 3 pixels wide and 4 high, whose first run covers more than two
+    // rows, with two pixels of the key color in the middle
+    static SPRITE: Sprite<'static> = Sprite {
+        width: 3,
+        height: 4,
+        pixels: Pixels::IndexedRle {
+            palette: &[0x1111, 0x2222, KEY],
+            runs: &[7, 0, 2, 2, 1, 1, 2, 0],
+        },
+        key: Some(KEY),
+    };
+
+    // The pixels of the sprite, with `BLANK` for the ones left out
+    const EXPECTED: [[u16; 3]; 4] = [
+        [0x1111, 0x1111, 0x1111],
+        [0x1111, 0x1111, 0x1111],
+        [0x1111, BLANK, BLANK],
+        [0x2222, 0x1111, 0x1111],
+    ];
+
+    // Blits the sprite onto a blank 8x6 frame buffer with a few columns to
+    // spare past its width, and checks every pixel of it
+    fn check_blit(x: i32, y: i32) {
+        let mut buffer = vec![BLANK; 12 * 6];
+        blit(&mut RamFrameBuffer::new(&mut buffer, 8, 6, 12), &SPRITE, x, y);
+
+        for py in 0 .. 6 {
+            for px in 0 .. 12 {
+                let (sx, sy) = (px - x, py - y);
+                let expected = if px < 8 && sx >= 0 && sx < 3 && sy >= 0 && sy < 4 {
+                    EXPECTED[sy as usize][sx as usize]
+                } else {
+                    BLANK
+                };
+                assert!(buffer[py as usize * 12 + px as usize] == expected,
+                        "pixel ({}, {}) of the sprite at ({}, {})", px, py, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn blit_draws_runs_across_rows_and_leaves_out_the_key() {
+        check_blit(0, 0);
+        check_blit(2, 1);
+        check_blit(5, 2);
+    }
+
+    #[test]
+    fn blit_is_clipped_at_the_edges() {
+        for &(x, y) in [(-1, 1), (-2, 1), (6, 1), (7, 1), (2, -1), (2, -3), (2, 3), (2, 5),
+                        (-2, -3), (7, 5), (-1, 4), (6, -2)].iter() {
+            check_blit(x, y);
+        }
+    }
+
+    #[test]
+    fn blit_off_screen_draws_nothing() {
+        for &(x, y) in [(-3, 1), (8, 1), (2, -4), (2, 6), (-100, -100), (100, 100)].iter() {
+            check_blit(x, y);
+        }
+    }
+
+    #[test]
+    fn blit_of_an_empty_sprite_draws_nothing() {
+        let mut buffer = vec![BLANK; 8 * 6];
+        let empty = Sprite { width: 0, height: 4, .. SPRITE };
+        blit(&mut RamFrameBuffer::new(&mut buffer, 8, 6, 8), &empty, 2, 2);
+        assert!(buffer.iter().all(|&pixel| pixel == BLANK));
+    }
+}
@@ -0,0 +1,342 @@
+use core::prelude::*;
+use core::fmt;
+
+use rand::Rng;
+
+use color;
+use display;
+use display::{WIDTH, HEIGHT};
+use draw;
+use framebuffer::FrameBuffer;
+use font::FONT_16X28;
+use highscore::HighScores;
+use raster;
+use raster::{Effect, Effects, Kind};
+use sprite;
+use sprite::TROPHY;
+use text;
+use text::Style;
+use theme::THEMES;
+use transition;
+use transition::Transition;
+
+use {GameEnv, Rectangle, redraw, restart, set_theme, settle, step};
+use controller::BUTTONS;
+
+// Number of frames each digit of the countdown is shown
+const COUNTDOWN_FRAMES: u32 = 50;
+// Number of frames a circle that hit an obstacle bursts for
+const BURST_FRAMES: u32 = 30;
+// Number of frames the game over screen is shown before the game can be restarted
+const GAME_OVER_FRAMES: u32 = 60;
+
+// Number of frames of the transitions from the title, to the game over screen
+// and from there to the next round
+const WIPE_FRAMES: u32 = 20;
+const FADE_FRAMES: u32 = 16;
+const IRIS_FRAMES: u32 = 24;
+
+// Line of the messages shown on top of the playing field, and of the theme
+// below the pause message
+const MESSAGE_Y: usize = (HEIGHT - 28) / 2;
+const THEME_Y: usize = MESSAGE_Y + 28;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum State {
+    Title,
+    Countdown,
+    Playing,
+    Paused,
+    Crashed,
+    GameOver,
+}
+
+pub struct Game {
+    env: GameEnv,
+    state: State,
+    // Number of frames spent in the current state
+    frames: u32,
+    // Buttons that were pressed in the previous frame
+    pressed: u32,
+    scores: HighScores,
+    // Place of the last round in the high score table
+    rank: Option<usize>,
+    // Index of the current theme in `THEMES`
+    theme: usize,
+    effects: Effects,
+    // The transition covering the screen, and the state entered once it is over
+    transition: Option<(Transition, State)>,
+    // The scroll offset of the background
+    offset: u32,
+}
+
+impl Game {
+    pub fn new<F: FrameBuffer, R: Rng>(fb: &mut F, rng: &mut R, players: usize, scores: HighScores) -> Game {
+        let mut game = Game {
+            env: restart(players, scores.best(), &THEMES[0], rng),
+            state: State::Title,
+            frames: 0,
+            pressed: 0,
+            scores: scores,
+            rank: None,
+            theme: 0,
+            effects: Effects::new(),
+            transition: None,
+            offset: 0,
+        };
+        game.enter(fb, State::Title);
+        game
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn env(&self) -> &GameEnv {
+        &self.env
+    }
+
+    /// The high score table, with every round played so far.
+    pub fn scores(&self) -> &HighScores {
+        &self.scores
+    }
+
+    /// Sets the scroll offset the background moves on to in the next frame
+    /// of a round that is played.
+    pub fn scroll(&mut self, offset: u32) {
+        self.offset = offset;
+    }
+
+    /// The raster effects of the current frame.
+    pub fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    /// Advances the game by a single frame.
+    ///
+    /// The buttons are active low, like the pins of the gamepad. Returns `true`
+    /// if a new round was started with `restart` during this frame.
+    pub fn update<F: FrameBuffer, R: Rng>(&mut self, fb: &mut F, rng: &mut R, buttons: u32) -> bool {
+        let pressed = !buttons & BUTTONS;
+        let new = pressed & !self.pressed;
+        self.pressed = pressed;
+        self.frames += 1;
+        self.env.dirty.clear();
+        self.effects.advance();
+
+        // Nothing else happens while a transition runs
+        if let Some((mut transition, next)) = self.transition.take() {
+            if transition.is_over() {
+                self.enter(fb, next);
+            } else {
+                transition.advance(fb);
+                self.transition = Some((transition, next));
+            }
+            return false;
+        }
+
+        match self.state {
+            State::Title => {
+                self.title(new);
+                false
+            },
+            State::Countdown => {
+                self.countdown(fb);
+                false
+            },
+            State::Playing => {
+                self.playing(fb, rng, buttons, pressed, new);
+                false
+            },
+            State::Paused => {
+                self.paused(fb, pressed, new);
+                false
+            },
+            State::Crashed => {
+                self.crashed(fb);
+                false
+            },
+            State::GameOver => self.game_over(rng, new),
+        }
+    }
+
+    fn title(&mut self, new: u32) {
+        if new != 0 {
+            let wipe = Transition::new(transition::Kind::VerticalWipe, WIPE_FRAMES, self.env.theme.background);
+            self.leave(wipe, State::Countdown);
+        }
+    }
+
+    fn countdown<F: FrameBuffer>(&mut self, fb: &mut F) {
+        if self.frames == 3 * COUNTDOWN_FRAMES {
+            self.enter(fb, State::Playing);
+        } else if self.frames % COUNTDOWN_FRAMES == 0 {
+            draw_countdown(fb, 3 - self.frames / COUNTDOWN_FRAMES, self.env.theme.text_style());
+        }
+    }
+
+    fn playing<F: FrameBuffer, R: Rng>(&mut self, fb: &mut F, rng: &mut R, buttons: u32, pressed: u32, new: u32) {
+        if self.pause_toggled(pressed, new) {
+            self.enter(fb, State::Paused);
+            return;
+        }
+
+        // The background goes beneath everything else drawn during the frame
+        self.env.background.scroll(fb, self.env.theme, self.offset, &mut self.env.dirty);
+        if !step(fb, &mut self.env, rng, buttons) {
+            self.rank = self.scores.insert(self.env.score);
+            self.enter(fb, State::Crashed);
+        }
+    }
+
+    // The pause menu, where the left and right buttons of the first circle pick the theme
+    fn paused<F: FrameBuffer>(&mut self, fb: &mut F, pressed: u32, new: u32) {
+        let keys = self.env.circles[0].keys;
+
+        if self.pause_toggled(pressed, new) {
+            self.enter(fb, State::Playing);
+        } else if new & (keys.left | keys.right) != 0 {
+            self.theme = if new & keys.left != 0 {
+                (self.theme + THEMES.len() - 1) % THEMES.len()
+            } else {
+                (self.theme + 1) % THEMES.len()
+            };
+
+            // The round is drawn again in the new colors once they have been
+            // wiped across the screen
+            set_theme(&mut self.env, &THEMES[self.theme]);
+            let wipe = Transition::new(transition::Kind::HorizontalWipe, WIPE_FRAMES, self.env.theme.background);
+            self.leave(wipe, State::Paused);
+        }
+    }
+
+    // Pressing up and down of the first circle at the same time toggles pause
+    fn pause_toggled(&self, pressed: u32, new: u32) -> bool {
+        let keys = self.env.circles[0].keys;
+        let pause = keys.up | keys.down;
+        pressed & pause == pause && new & pause != 0
+    }
+
+    // The round stands still while the circle that hit the obstacle bursts,
+    // then fades out
+    fn crashed<F: FrameBuffer>(&mut self, fb: &mut F) {
+        if self.frames < BURST_FRAMES {
+            settle(fb, &mut self.env);
+        } else {
+            let fade = Transition::new(transition::Kind::FadeToBlack, FADE_FRAMES, color::BLACK);
+            self.leave(fade, State::GameOver);
+        }
+    }
+
+    fn game_over<R: Rng>(&mut self, rng: &mut R, new: u32) -> bool {
+        if self.frames < GAME_OVER_FRAMES || new == 0 {
+            return false;
+        }
+
+        // The new round is drawn once the iris has closed over the old one
+        let players = self.env.circles.len();
+        self.env = restart(players, self.env.max_score, &THEMES[self.theme], rng);
+        let iris = transition::Kind::Iris(WIDTH as i32 / 2, HEIGHT as i32 / 2);
+        self.leave(Transition::new(iris, IRIS_FRAMES, self.env.theme.background), State::Countdown);
+        true
+    }
+
+    // Covers the screen with a transition, and enters a state once it is over
+    fn leave(&mut self, transition: Transition, next: State) {
+        self.transition = Some((transition, next));
+    }
+
+    // Switches to a new state and draws everything that stays on screen while in it
+    fn enter<F: FrameBuffer>(&mut self, fb: &mut F, state: State) {
+        let previous = self.state;
+        self.state = state;
+        self.frames = 0;
+
+        let theme = self.env.theme;
+        let style = theme.text_style();
+
+        let message = Rectangle::new(0, MESSAGE_Y as i32, WIDTH as i32, 2 * FONT_16X28.height as i32);
+        match state {
+            State::Playing | State::Paused => self.env.dirty.mark(message),
+            _ => {
+                self.env.dirty.mark_all();
+                self.effects.stop_all();
+            },
+        }
+
+        match state {
+            State::Title => {
+                display::clear(fb, theme.background);
+                draw_centered(fb, 70, format_args!("CIRCLE GAME"), style);
+                draw_centered(fb, 140, format_args!("PRESS A BUTTON"), style);
+                self.effects.start(Effect {
+                    kind: Kind::Wave,
+                    band: raster::Band { top: 70, bottom: 70 + FONT_16X28.height },
+                    frames: raster::FOREVER,
+                    intensity: 3,
+                });
+            },
+            State::Countdown => {
+                display::clear(fb, theme.background);
+                self.env.background.draw(fb, theme);
+                for circle in self.env.circles.iter() {
+                    display::draw_circle(fb, circle);
+                }
+                self.env.score_field.redraw(fb, self.env.score);
+                self.env.max_score_field.redraw(fb, self.env.max_score);
+                draw_countdown(fb, 3, style);
+                // The playing field slides in under the score
+                self.effects.start(Effect {
+                    kind: Kind::Slide,
+                    band: raster::PLAYFIELD,
+                    frames: 25,
+                    intensity: raster::MAX_OFFSET / 2,
+                });
+            },
+            State::Playing => {
+                // Erase the countdown or pause menu
+                draw::fill_rect(fb, &message, theme.background.0);
+            },
+            State::Paused => {
+                // Staying paused means another theme was picked
+                if previous == State::Paused {
+                    redraw(fb, &mut self.env);
+                }
+                draw_centered(fb, MESSAGE_Y, format_args!("PAUSED"), style);
+                draw_centered(fb, THEME_Y, format_args!("< {} >", theme.name), style);
+            },
+            State::Crashed => {
+                // The round stays on screen while the circle bursts
+                self.effects.start(Effect {
+                    kind: Kind::Shake,
+                    band: raster::SCREEN,
+                    frames: 40,
+                    intensity: 8,
+                });
+            },
+            State::GameOver => {
+                display::clear(fb, theme.background);
+                draw_centered(fb, 50, format_args!("GAME OVER"), style);
+                draw_centered(fb, 110, format_args!("SCORE {:3}", self.env.score), style);
+                draw_centered(fb, 150, format_args!("BEST  {:3}", self.env.max_score), style);
+                if let Some(rank) = self.rank {
+                    // With a trophy beside it, as the round made it into the table
+                    let x = draw_centered(fb, 190, format_args!("RANK  {:3}", rank + 1), style);
+                    let y = 190 + (FONT_16X28.height as i32 - TROPHY.height as i32) / 2;
+                    sprite::blit(fb, &TROPHY, x - TROPHY.width as i32 - 8, y);
+                }
+            },
+        }
+    }
+}
+
+fn draw_countdown<F: FrameBuffer>(fb: &mut F, number: u32, style: Style) {
+    draw_centered(fb, MESSAGE_Y, format_args!("{}", number), style);
+}
+
+// Draws a line of text in the middle of the screen, and returns where it starts
+fn draw_centered<F: FrameBuffer>(fb: &mut F, y: usize, args: fmt::Arguments, style: Style) -> i32 {
+    let x = (WIDTH as i32 - text::fmt_width(args, style) as i32) / 2;
+    text::draw_fmt(fb, x, y as i32, args, style);
+    x
+}
@@ -0,0 +1,136 @@
+// Text rendering with the bitmap fonts, without allocating.
+//
+// Text is drawn from a `&str` or from `format_args!` through `TextWriter`, an
+// implementation of `fmt::Write` that draws every character it is given
+// straight into the frame buffer. Characters a font has no glyph for are drawn
+// as its default glyph, and glyphs are clipped at the edges of the screen.
+// Small fonts can be scaled up, which draws every pixel as a square block.
+
+use core::prelude::*;
+use core::fmt;
+use core::fmt::Write;
+
+use Rectangle;
+use color;
+use color::Rgb565;
+use draw;
+use framebuffer::FrameBuffer;
+use font::{Font, FONT_16X28};
+
+#[derive(Copy, Clone)]
+pub struct Style {
+    pub font: &'static Font,
+    /// Width and height of the block every pixel of the font is drawn as.
+    pub scale: usize,
+    pub fg: Rgb565,
+    /// The color around the strokes of the glyphs, or `None` to leave the
+    /// pixels there as they are.
+    pub bg: Option<Rgb565>,
+}
+
+/// White text on black in the large font.
+pub static DEFAULT: Style = Style { font: &FONT_16X28, scale: 1, fg: color::WHITE, bg: Some(color::BLACK) };
+
+/// Draws text one character after the other, moving to the right.
+pub struct TextWriter<'a, F: 'a> {
+    fb: &'a mut F,
+    x: i32,
+    y: i32,
+    style: Style,
+}
+
+impl<'a, F: FrameBuffer> TextWriter<'a, F> {
+    pub fn new(fb: &'a mut F, x: i32, y: i32, style: Style) -> TextWriter<'a, F> {
+        TextWriter {
+            fb: fb,
+            x: x,
+            y: y,
+            style: style,
+        }
+    }
+
+    /// Where the next character goes.
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+}
+
+impl<'a, F: FrameBuffer> fmt::Write for TextWriter<'a, F> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for c in text.chars() {
+            self.x += draw_char(self.fb, self.x, self.y, c, self.style);
+        }
+        Ok(())
+    }
+}
+
+/// Draws a string, and returns the x coordinate just past its end.
+pub fn draw_str<F: FrameBuffer>(fb: &mut F, x: i32, y: i32, text: &str, style: Style) -> i32 {
+    let mut writer = TextWriter::new(fb, x, y, style);
+    let _ = writer.write_str(text);
+    writer.x()
+}
+
+/// Draws formatted text, e.g. `draw_fmt(fb, x, y, format_args!("{} fps", fps), style)`.
+pub fn draw_fmt<F: FrameBuffer>(fb: &mut F, x: i32, y: i32, args: fmt::Arguments, style: Style) -> i32 {
+    let mut writer = TextWriter::new(fb, x, y, style);
+    let _ = writer.write_fmt(args);
+    writer.x()
+}
+
+/// The width of formatted text in pixels.
+pub fn fmt_width(args: fmt::Arguments, style: Style) -> usize {
+    let mut measure = Measure { font: style.font, width: 0 };
+    let _ = measure.write_fmt(args);
+    measure.width * style.scale
+}
+
+struct Measure<'a> {
+    font: &'a Font,
+    width: usize,
+}
+
+impl<'a> fmt::Write for Measure<'a> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.width += self.font.width(text);
+        Ok(())
+    }
+}
+
+/// Draws a single character with the top left of its line at `(x, y)`, and
+/// returns how far the next character is to the right.
+pub fn draw_char<F: FrameBuffer>(fb: &mut F, x: i32, y: i32, c: char, style: Style) -> i32 {
+    let font = style.font;
+    let glyph = font.glyph(c);
+    let scale = style.scale as i32;
+
+    // With a background the whole line height is drawn, otherwise only the bitmap
+    let (left, top, right, bottom) = match style.bg {
+        Some(_) => (0, 0, glyph.advance as i32, font.height as i32),
+        None => {
+            let (left, top) = (glyph.left as i32, glyph.top as i32);
+            (left, top, left + glyph.width as i32, top + glyph.height as i32)
+        },
+    };
+
+    // Runs of set or clear pixels are filled at once, and clipped when they are drawn
+    for row in top .. bottom {
+        let mut col = left;
+        while col < right {
+            let set = font.pixel(glyph, col, row);
+            let mut end = col + 1;
+            while end < right && font.pixel(glyph, end, row) == set {
+                end += 1;
+            }
+
+            let color = if set { Some(style.fg) } else { style.bg };
+            if let Some(color) = color {
+                let run = Rectangle::new(x + col * scale, y + row * scale, (end - col) * scale, scale);
+                draw::fill_rect(fb, &run, color.0);
+            }
+            col = end;
+        }
+    }
+
+    glyph.advance as i32 * scale
+}
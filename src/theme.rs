@@ -0,0 +1,126 @@
+// The colors of everything on screen.
+//
+// The theme can be switched from the pause menu, with the left and right
+// buttons of the first circle, and applies from the next frame on.
+
+use core::prelude::*;
+
+use MAX_CIRCLES;
+use animation::Profile;
+use color;
+use color::Rgb565;
+use font::{FONT_16X28, FONT_3X5};
+use text::Style;
+
+pub struct Theme {
+    pub name: &'static str,
+    pub background: Rgb565,
+    /// The pattern of the scrolling background, which is left out when it is
+    /// the color of the background.
+    pub pattern: Rgb565,
+    /// Messages and the frame rate.
+    pub text: Rgb565,
+    pub score: Rgb565,
+    pub max_score: Rgb565,
+    pub circles: [Profile; MAX_CIRCLES],
+    /// Leave particles fading out behind the circles as they move.
+    pub trails: bool,
+    /// The leading line of an obstacle, and the lines fading out above it.
+    pub obstacles: [Rgb565; 3],
+}
+
+impl Theme {
+    /// Messages in the large font.
+    pub fn text_style(&self) -> Style {
+        Style { font: &FONT_16X28, scale: 1, fg: self.text, bg: Some(self.background) }
+    }
+
+    /// The score in the corner, in the small font.
+    pub fn score_style(&self) -> Style {
+        Style { font: &FONT_3X5, scale: 2, fg: self.score, bg: Some(self.background) }
+    }
+
+    pub fn max_score_style(&self) -> Style {
+        Style { fg: self.max_score, .. self.score_style() }
+    }
+}
+
+// The profile of a circle that runs around its outline as a rainbow, turning
+// by a point of the outline every frame, starting from the hue of `color`
+macro_rules! rainbow {
+    ($color:expr) => (Profile {
+        color: Rgb565($color),
+        hue_speed: -40,
+        hue_step: 40,
+        saturation_swing: 0,
+        value_swing: 0,
+        swing_frames: 1,
+    })
+}
+
+// The profile of a circle in a single color, whose brightness swings down by
+// `value_swing` and back up every `swing_frames` frames, or never changes
+macro_rules! solid {
+    ($color:expr) => (solid!($color, 0, 1));
+    ($color:expr, $value_swing:expr, $swing_frames:expr) => (Profile {
+        color: Rgb565($color),
+        hue_speed: 0,
+        hue_step: 0,
+        saturation_swing: 0,
+        value_swing: $value_swing,
+        swing_frames: $swing_frames,
+    })
+}
+
+pub static THEMES: [Theme; 3] = [CLASSIC, HIGH_CONTRAST, PAPER];
+
+/// The colors the game always had, running around the circles as a rainbow.
+pub const CLASSIC: Theme = Theme {
+    name: "CLASSIC",
+    background: color::BLACK,
+    pattern: Rgb565(0x2104),
+    text: color::WHITE,
+    score: color::WHITE,
+    max_score: Rgb565(0x2ee0),
+    circles: [rainbow!(2000), rainbow!(12000), rainbow!(22000), rainbow!(42000)],
+    trails: true,
+    obstacles: [Rgb565(0xf800), Rgb565(0xe000), Rgb565(0x0040)],
+};
+
+/// Solid colors from the Okabe-Ito palette, which stay apart with every kind
+/// of color blindness, on black with white obstacles.
+pub const HIGH_CONTRAST: Theme = Theme {
+    name: "CONTRAST",
+    background: color::BLACK,
+    // Nothing to tell apart from the circles and obstacles
+    pattern: color::BLACK,
+    text: color::WHITE,
+    score: color::WHITE,
+    // Yellow
+    max_score: Rgb565(0xf728),
+    // Orange, sky blue, bluish green and reddish purple, which never change
+    circles: [solid!(0xe4e0), solid!(0x55bd), solid!(0x04ee), solid!(0xcbd4)],
+    // Nothing moving around that isn't a circle or an obstacle
+    trails: false,
+    obstacles: [color::WHITE, color::GREY, Rgb565(0x4208)],
+};
+
+/// Dark colors on a light background, with the circles slowly fading in and out.
+pub const PAPER: Theme = Theme {
+    name: "PAPER",
+    background: Rgb565(0xf77c),
+    pattern: Rgb565(0xdeb9),
+    text: color::BLACK,
+    score: color::BLACK,
+    // Blue
+    max_score: Rgb565(0x0396),
+    // Vermillion, blue, bluish green and reddish purple
+    circles: [
+        solid!(0xd2e0, 96, 120),
+        solid!(0x0396, 96, 120),
+        solid!(0x04ee, 96, 120),
+        solid!(0xcbd4, 96, 120),
+    ],
+    trails: true,
+    obstacles: [color::BLACK, color::GREY, Rgb565(0xc618)],
+};
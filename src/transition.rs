@@ -0,0 +1,230 @@
+// Transitions, which cover the screen bit by bit when the game moves from one
+// state to another.
+//
+// A transition runs for a number of frames, and every call to `advance` only
+// draws what it covers in one more frame, so the main loop keeps running and
+// the frame rate keeps counting in the meantime. Once it is over the screen is
+// a single color, and the next state can draw over it.
+//
+// The fade darkens the pixels on screen by scaling their channels, which only
+// works on a frame buffer that can be read back. It takes every `FADE_STRIDE`th
+// line in turn, a step further down each time, so a frame only reads and
+// writes part of the screen and the drawing stays within what the double
+// buffer remembers. The others cover the screen with a color, from one edge to
+// the other or from the edges in towards a point.
+
+use core::prelude::*;
+use core::cmp;
+
+use Rectangle;
+use color::Rgb565;
+use display;
+use draw;
+use framebuffer::FrameBuffer;
+
+// Lines darkened in each frame of a fade, one out of this many
+const FADE_STRIDE: u32 = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    /// Darkens the whole screen down to black.
+    FadeToBlack,
+    /// Covers the screen from the left edge to the right one.
+    HorizontalWipe,
+    /// Covers the screen from the top down.
+    VerticalWipe,
+    /// A circle closing in on a point, covering everything outside of it.
+    Iris(i32, i32),
+}
+
+pub struct Transition {
+    kind: Kind,
+    // The color the screen is covered with, which the fade leaves out
+    color: Rgb565,
+    frames: u32,
+    // Frames drawn so far
+    frame: u32,
+}
+
+impl Transition {
+    /// A transition that covers the screen in `frames` frames, at least one,
+    /// or `FADE_STRIDE` for a fade, which takes that many to reach every line.
+    pub fn new(kind: Kind, frames: u32, color: Rgb565) -> Transition {
+        let min = match kind {
+            Kind::FadeToBlack => FADE_STRIDE,
+            _ => 1,
+        };
+
+        Transition {
+            kind: kind,
+            color: color,
+            frames: cmp::max(frames, min),
+            frame: 0,
+        }
+    }
+
+    /// Whether the screen is covered, after the last frame was drawn.
+    pub fn is_over(&self) -> bool {
+        self.frame == self.frames
+    }
+
+    /// Draws the next frame of the transition, if it isn't over yet.
+    pub fn advance<F: FrameBuffer>(&mut self, fb: &mut F) {
+        if self.is_over() {
+            return;
+        }
+        self.frame += 1;
+
+        let screen = display::clip_rect(fb);
+        // How far the transition was and is along, out of `frames`
+        let (last, next) = (self.frame - 1, self.frame);
+
+        match self.kind {
+            Kind::FadeToBlack => {
+                // Each line is darkened to where the fade is by the time its
+                // turn comes around again, so all of them are black at the end
+                let level = |frame: u32| self.frames - cmp::min(frame, self.frames);
+                let before = if next > FADE_STRIDE { level(last) } else { self.frames };
+                let first = (last % FADE_STRIDE) as usize;
+                fade(fb, first, level(next + FADE_STRIDE - 1), before);
+            },
+            Kind::HorizontalWipe => {
+                let (from, to) = (self.part(screen.width, last), self.part(screen.width, next));
+                draw::fill_rect(fb, &Rectangle::new(from, 0, to - from, screen.height), self.color.0);
+            },
+            Kind::VerticalWipe => {
+                let (from, to) = (self.part(screen.height, last), self.part(screen.height, next));
+                draw::fill_rect(fb, &Rectangle::new(0, from, screen.width, to - from), self.color.0);
+            },
+            Kind::Iris(x, y) => {
+                // Large enough for the circle to start out around the whole screen
+                let corners = [(0, 0), (screen.width, 0), (0, screen.height), (screen.width, screen.height)];
+                let furthest = corners.iter().map(|&(cx, cy)| (cx - x) * (cx - x) + (cy - y) * (cy - y)).max();
+                let radius = sqrt(furthest.unwrap_or(0)) + 1;
+
+                let outer = radius - self.part(radius, last);
+                let inner = radius - self.part(radius, next);
+                iris(fb, &screen, (x, y), outer, inner, self.color.0);
+            },
+        }
+    }
+
+    // The part of `total` covered after a number of frames
+    fn part(&self, total: i32, frames: u32) -> i32 {
+        total * frames as i32 / self.frames as i32
+    }
+}
+
+// Scales the pixels of every `FADE_STRIDE`th line from `first` on down from
+// `before` to `after` of their brightness, leaving the ones it doesn't change
+fn fade<F: FrameBuffer>(fb: &mut F, first: usize, after: u32, before: u32) {
+    let amount = (255 * after / before) as u8;
+    let mut y = first;
+    while y < fb.height() {
+        for x in 0 .. fb.width() {
+            let idx = y * fb.stride() + x;
+            let color = fb.read(idx);
+            let faded = Rgb565(color).scale(amount).0;
+            if faded != color {
+                fb.write(idx, faded);
+            }
+        }
+        y += FADE_STRIDE as usize;
+    }
+}
+
+// Covers the ring between two circles around `center`, line by line
+fn iris<F: FrameBuffer>(fb: &mut F, screen: &Rectangle, center: (i32, i32), outer: i32, inner: i32, color: u16) {
+    let (cx, cy) = center;
+    for y in screen.dy .. screen.dy + screen.height {
+        let dy = y - cy;
+        let outer_width = match half_width(outer, dy) {
+            Some(width) => width,
+            None => continue,
+        };
+
+        match half_width(inner, dy) {
+            Some(inner_width) if inner_width < outer_width => {
+                draw::hline(fb, cx - outer_width, cx - inner_width - 1, y, color);
+                draw::hline(fb, cx + inner_width + 1, cx + outer_width, y, color);
+            },
+            Some(_) => {},
+            None => draw::hline(fb, cx - outer_width, cx + outer_width, y, color),
+        }
+    }
+}
+
+// How far a circle reaches to either side of its center on the line `dy`
+// below it, if it reaches that line at all
+fn half_width(radius: i32, dy: i32) -> Option<i32> {
+    if radius <= 0 || dy.abs() >= radius {
+        None
+    } else {
+        Some(sqrt(radius * radius - dy * dy))
+    }
+}
+
+// The square root rounded down, with Newton's method
+fn sqrt(value: i32) -> i32 {
+    if value <= 0 {
+        return 0;
+    }
+
+    let mut root = value;
+    let mut next = (root + 1) / 2;
+    while next < root {
+        root = next;
+        next = (root + value / root) / 2;
+    }
+    root
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{Kind, Transition};
+    use color;
+    use framebuffer::RamFrameBuffer;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 24;
+
+    // Runs a fade over a screen of white and grey lines until it is over
+    fn fade(frames: u32) -> Vec<u16> {
+        let mut buffer = vec![0; WIDTH * HEIGHT];
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            *pixel = if i / WIDTH % 3 == 0 { color::GREY.0 } else { color::WHITE.0 };
+        }
+
+        {
+            let mut fb = RamFrameBuffer::new(&mut buffer, WIDTH, HEIGHT, WIDTH);
+            let mut transition = Transition::new(Kind::FadeToBlack, frames, color::BLACK);
+            let mut drawn = 0;
+            while !transition.is_over() {
+                transition.advance(&mut fb);
+                drawn += 1;
+                assert!(drawn <= 100);
+            }
+        }
+        buffer
+    }
+
+    #[test]
+    fn fade_ends_black_on_every_line() {
+        for &frames in [1, 2, 3, 16].iter() {
+            let buffer = fade(frames);
+            assert!(buffer.iter().all(|&pixel| pixel == color::BLACK.0), "{} frames", frames);
+        }
+    }
+
+    #[test]
+    fn fade_darkens_the_screen_bit_by_bit() {
+        let mut buffer = vec![color::WHITE.0; WIDTH * HEIGHT];
+        let mut fb = RamFrameBuffer::new(&mut buffer, WIDTH, HEIGHT, WIDTH);
+        let mut transition = Transition::new(Kind::FadeToBlack, 16, color::BLACK);
+
+        transition.advance(&mut fb);
+        assert!(!transition.is_over());
+        assert!(fb.buffer().iter().any(|&pixel| pixel == color::WHITE.0));
+        assert!(fb.buffer().iter().any(|&pixel| pixel != color::WHITE.0));
+    }
+}
@@ -0,0 +1,150 @@
+// Receives the screenshots the game sends over the RS-232 port of the DK3750,
+// and writes each of them to a BMP file of its own.
+//
+// Usage: screenshot <port> [directory]
+//
+// The port has to be set up beforehand, e.g. on Linux with
+// `stty -F /dev/ttyUSB0 115200 raw`, and `-` reads from the standard input
+// instead. Anything sent between screenshots is skipped. The files are named
+// `screenshot-000.bmp` and up, leaving the ones already in the directory alone.
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Length of the headers and masks before the pixels, as the game writes them
+const HEADER_LEN: usize = 66;
+// Largest file taken for a screenshot, well beyond the 320x240 of the TFT
+const MAX_LEN: usize = 4 * 1024 * 1024;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        fail("usage: screenshot <port> [directory]");
+    }
+    let port = &args[1];
+    let dir = Path::new(if args.len() == 3 { &args[2][..] } else { "." });
+
+    let input: Box<Read> = if port == "-" {
+        Box::new(io::stdin())
+    } else {
+        match File::open(port) {
+            Ok(file) => Box::new(file),
+            Err(e) => fail(&format!("{}: {}", port, e)),
+        }
+    };
+    let mut input = BufReader::new(input);
+
+    let mut number = 0;
+    loop {
+        let bmp = match receive(&mut input) {
+            Ok(Some(bmp)) => bmp,
+            Ok(None) => break,
+            Err(e) => fail(&format!("{}: {}", port, e)),
+        };
+
+        let path = next_path(dir, &mut number);
+        match File::create(&path).and_then(|mut file| file.write_all(&bmp)) {
+            Ok(()) => println!("{}", path.display()),
+            Err(e) => fail(&format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+// Reads up to the end of the next screenshot, or returns `None` at the end of the input
+fn receive<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut bmp = Vec::new();
+    let mut len = None;
+    while len.is_none() {
+        if !try!(read_up_to(input, &mut bmp, HEADER_LEN)) {
+            return Ok(None);
+        }
+
+        len = file_len(&bmp);
+        if len.is_none() {
+            // Start over from the next "BM", which may be among the bytes already read
+            let start = (1 .. bmp.len())
+                .find(|&i| bmp[i] == b'B' && (i + 1 == bmp.len() || bmp[i + 1] == b'M'))
+                .unwrap_or(bmp.len());
+            bmp = bmp[start ..].to_vec();
+        }
+    }
+
+    if !try!(read_up_to(input, &mut bmp, len.unwrap())) {
+        let _ = writeln!(io::stderr(), "the input ended in the middle of a screenshot");
+        return Ok(None);
+    }
+    Ok(Some(bmp))
+}
+
+// The length of the whole file if the header is one the game writes
+fn file_len(header: &[u8]) -> Option<usize> {
+    if header[0] != b'B' || header[1] != b'M' {
+        return None;
+    }
+
+    let len = read_u32(&header[2 ..]) as usize;
+    let offset = read_u32(&header[10 ..]) as usize;
+    let info_len = read_u32(&header[14 ..]);
+    let bits = read_u16(&header[28 ..]);
+    let compression = read_u32(&header[30 ..]);
+
+    // 16 bits per pixel, with bit masks
+    if len > HEADER_LEN && len <= MAX_LEN && offset == HEADER_LEN && info_len == 40 &&
+        bits == 16 && compression == 3 {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+// Reads until `data` is `len` bytes long, returning `false` if the input ends first
+fn read_up_to<R: Read>(input: &mut R, data: &mut Vec<u8>, len: usize) -> io::Result<bool> {
+    while data.len() < len {
+        match try!(read_byte(input)) {
+            Some(byte) => data.push(byte),
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    data[0] as u16 | (data[1] as u16) << 8
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    read_u16(data) as u32 | (read_u16(&data[2 ..]) as u32) << 16
+}
+
+// The first file name after `number` that is not taken yet
+fn next_path(dir: &Path, number: &mut u32) -> PathBuf {
+    loop {
+        let path = dir.join(&format!("screenshot-{:03}.bmp", *number));
+        *number += 1;
+        if fs::metadata(&path).is_err() {
+            return path;
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    process::exit(1)
+}